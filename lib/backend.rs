@@ -0,0 +1,183 @@
+use crate::cache::unix_time;
+use crate::error::CSDError;
+use crate::exec::call_ceph;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Something that answers ceph mon commands, such as `osd dump`, with their
+// JSON output. `DiagMap::from_backend` builds a map from any of them.
+pub trait CephBackend {
+    fn mon_command(&self, cmd: &str) -> Result<String, CSDError>;
+
+    // When the answers are from, in seconds since the Unix epoch. A live
+    // cluster answers with its state as of now.
+    fn collected_at(&self) -> u64 {
+        unix_time()
+    }
+}
+
+// Runs the `ceph` command line tool
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CliBackend;
+
+impl CephBackend for CliBackend {
+    fn mon_command(&self, cmd: &str) -> Result<String, CSDError> {
+        call_ceph(cmd)
+    }
+}
+
+// Answers from a directory of recorded responses, one file per command named
+// after it with spaces turned into underscores, e.g. `osd_crush_dump.json`
+#[derive(Debug, Clone)]
+pub struct RecordedBackend {
+    dir: PathBuf,
+}
+
+impl RecordedBackend {
+    pub fn new<P: AsRef<Path>>(dir: P) -> RecordedBackend {
+        RecordedBackend {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // The file the response to `cmd` is recorded in
+    pub fn path(&self, cmd: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", cmd.replace(' ', "_")))
+    }
+}
+
+impl CephBackend for RecordedBackend {
+    fn mon_command(&self, cmd: &str) -> Result<String, CSDError> {
+        let path = self.path(cmd);
+        debug!("reading recorded {} from {}", cmd, path.display());
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {
+                return Err(CSDError::CephExecError(format!(
+                    "no recorded response for `{}` in {}",
+                    cmd,
+                    self.dir.display()
+                )))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let mut buffer = String::new();
+        file.read_to_string(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+// Answers with responses scripted in memory. Each command replays its
+// responses in order and keeps repeating the last one, so a test can script a
+// cluster that changes between polls. Every command asked for is logged.
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    responses: Mutex<BTreeMap<String, VecDeque<String>>>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        MockBackend::default()
+    }
+
+    // Queue `response` as the next answer to `cmd`
+    pub fn respond(self, cmd: &str, response: &str) -> MockBackend {
+        if let Ok(mut responses) = self.responses.lock() {
+            responses
+                .entry(cmd.to_string())
+                .or_insert_with(VecDeque::new)
+                .push_back(response.to_string());
+        }
+        self
+    }
+
+    // Queue the contents of the file at `path` as the next answer to `cmd`
+    pub fn respond_from_file(self, cmd: &str, path: &str) -> Result<MockBackend, CSDError> {
+        let mut file = File::open(path)?;
+        let mut buffer = String::new();
+        file.read_to_string(&mut buffer)?;
+        Ok(self.respond(cmd, &buffer))
+    }
+
+    // Every command asked for so far, oldest first
+    pub fn calls(&self) -> Vec<String> {
+        match self.calls.lock() {
+            Ok(calls) => calls.clone(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl CephBackend for MockBackend {
+    fn mon_command(&self, cmd: &str) -> Result<String, CSDError> {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(cmd.to_string());
+        }
+        let mut responses = self
+            .responses
+            .lock()
+            .map_err(|_| CSDError::CephExecError("mock backend poisoned".to_string()))?;
+        match responses.get_mut(cmd) {
+            Some(queue) if queue.len() > 1 => Ok(queue.pop_front().unwrap_or_default()),
+            Some(queue) if !queue.is_empty() => Ok(queue[0].clone()),
+            _ => Err(CSDError::CephExecError(format!(
+                "no scripted response for `{}`",
+                cmd
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from::FromCeph;
+    use crate::osdmap::OsdMap;
+
+    #[test]
+    fn recorded_backend_path() {
+        let backend = RecordedBackend::new("test/luminous/recorded");
+        assert_eq!(
+            backend.path("osd crush dump"),
+            Path::new("test/luminous/recorded/osd_crush_dump.json")
+        );
+    }
+
+    #[test]
+    fn recorded_backend_osd_dump() {
+        let backend = RecordedBackend::new("test/luminous/recorded");
+        let osd_map = OsdMap::from_backend(&backend, "osd dump").unwrap();
+        assert_eq!(osd_map.osds.len(), 3);
+    }
+
+    #[test]
+    fn recorded_backend_missing() {
+        let backend = RecordedBackend::new("test/luminous/recorded");
+        match backend.mon_command("osd tree") {
+            Err(CSDError::CephExecError(err)) => assert!(err.contains("osd tree")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mock_backend_scripted() {
+        let backend = MockBackend::new()
+            .respond("status", "{\"epoch\": 1}")
+            .respond("status", "{\"epoch\": 2}");
+        assert_eq!(backend.mon_command("status").unwrap(), "{\"epoch\": 1}");
+        assert_eq!(backend.mon_command("status").unwrap(), "{\"epoch\": 2}");
+        // The last response sticks
+        assert_eq!(backend.mon_command("status").unwrap(), "{\"epoch\": 2}");
+        assert!(backend.mon_command("osd dump").is_err());
+        assert_eq!(
+            backend.calls(),
+            vec!["status", "status", "status", "osd dump"]
+        );
+    }
+}
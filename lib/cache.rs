@@ -0,0 +1,127 @@
+use crate::backend::CephBackend;
+use crate::diag::DiagMap;
+use crate::error::CSDError;
+
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Seconds since the Unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// A `DiagMap` and when its maps were fetched
+#[derive(Debug)]
+pub struct CachedDiag {
+    pub diag_map: DiagMap,
+    // Seconds since the Unix epoch
+    pub collected_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    diag: Option<Arc<CachedDiag>>,
+    last_error: Option<String>,
+}
+
+// The latest `DiagMap` for long running modes, shared between the thread that
+// refreshes it and the ones that answer requests. A failed refresh keeps the
+// previous maps around.
+#[derive(Debug, Clone, Default)]
+pub struct DiagCache {
+    state: Arc<RwLock<CacheState>>,
+}
+
+impl DiagCache {
+    pub fn new() -> DiagCache {
+        DiagCache::default()
+    }
+
+    // Fetch the maps from `backend` again, as old as the backend says they are
+    pub fn refresh<B: CephBackend + ?Sized>(&self, backend: &B) -> Result<(), CSDError> {
+        let collected_at = backend.collected_at();
+        let fetched = DiagMap::from_backend(backend);
+        let mut state = match self.state.write() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match fetched {
+            Ok(diag_map) => {
+                state.diag = Some(Arc::new(CachedDiag {
+                    diag_map,
+                    collected_at,
+                }));
+                state.last_error = None;
+                Ok(())
+            }
+            Err(err) => {
+                state.last_error = Some(err.to_string());
+                Err(err)
+            }
+        }
+    }
+
+    // Refresh from `backend` every `interval`, forever. Failures are logged
+    // and the previous maps kept.
+    pub fn refresh_every<B: CephBackend + ?Sized>(&self, backend: &B, interval: Duration) -> ! {
+        loop {
+            if let Err(err) = self.refresh(backend) {
+                warn!("Refreshing the maps failed, {}", err);
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    // The latest maps, if any refresh has succeeded yet
+    pub fn get(&self) -> Option<Arc<CachedDiag>> {
+        match self.state.read() {
+            Ok(state) => state.diag.clone(),
+            Err(poisoned) => poisoned.into_inner().diag.clone(),
+        }
+    }
+
+    // Why the last refresh failed, if it did
+    pub fn last_error(&self) -> Option<String> {
+        match self.state.read() {
+            Ok(state) => state.last_error.clone(),
+            Err(poisoned) => poisoned.into_inner().last_error.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+
+    #[test]
+    fn cache_keeps_last_good_maps() {
+        let backend = MockBackend::new()
+            .respond_from_file("osd dump", "test/jewel/osd_dump_safe.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_safe.json"))
+            .and_then(|backend| backend.respond_from_file("osd crush dump", "test/jewel/osd_crush_dump.json"))
+            .unwrap()
+            .respond("pg dump", "not json");
+        let cache = DiagCache::new();
+        assert!(cache.get().is_none());
+        cache.refresh(&backend).unwrap();
+        assert!(cache.last_error().is_none());
+        assert!(cache.refresh(&backend).is_err());
+        assert!(cache.last_error().is_some());
+        assert_eq!(cache.get().unwrap().diag_map.osd_map.epoch, 23);
+    }
+
+    #[test]
+    fn cache_snapshot_collected_at() {
+        use crate::snapshot::Snapshot;
+        let cache = DiagCache::new();
+        cache
+            .refresh(&Snapshot::open("test/luminous/snapshot_racks.json").unwrap())
+            .unwrap();
+        assert_eq!(cache.get().unwrap().collected_at, 1543859445);
+    }
+}
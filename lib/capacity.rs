@@ -0,0 +1,358 @@
+use ansi_term::Colour;
+use crate::diag::{DiagMap, Format, Status};
+use crate::error::CSDError;
+use crate::osdmap::Pools;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// Ceph's defaults for `mon_osd_nearfull_ratio`, `mon_osd_backfillfull_ratio`
+// and `mon_osd_full_ratio`, for when the maps don't carry them
+const NEARFULL_RATIO: f64 = 0.85;
+const BACKFILLFULL_RATIO: f64 = 0.90;
+const FULL_RATIO: f64 = 0.95;
+
+// The highest ratio an OSD's utilisation reaches
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Threshold {
+    Nearfull,
+    Backfillfull,
+    Full,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OsdCapacity {
+    pub osd: i32,
+    pub kb: u64,
+    pub kb_used: u64,
+    // Data backfilled onto the OSD once the removed OSDs are out
+    pub kb_added: u64,
+    pub utilisation: f64,
+    pub projected: f64,
+    // The highest ratio the backfill pushes the OSD past
+    pub threshold: Option<Threshold>,
+    // The highest ratio the OSD is already past, whatever is removed
+    pub current: Option<Threshold>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CapacityProjection {
+    pub osds: Vec<i32>,
+    pub status: Status,
+    pub nearfull_ratio: f64,
+    pub backfillfull_ratio: f64,
+    pub full_ratio: f64,
+    // Every surviving OSD, fullest first
+    pub projected: Vec<OsdCapacity>,
+}
+
+impl CapacityProjection {
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => self.print_pretty(),
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+
+    fn print_pretty(&self) {
+        let osds: Vec<String> = self.osds.iter().map(|id| id.to_string()).collect();
+        println!(
+            "Projected utilisation without OSDs {} (nearfull {:.0}%, backfillfull {:.0}%):",
+            osds.join(", "),
+            self.nearfull_ratio * 100.0,
+            self.backfillfull_ratio * 100.0
+        );
+        for osd in &self.projected {
+            let colour = match (osd.threshold, osd.current) {
+                (Some(_), _) => Colour::Red,
+                (None, Some(_)) => Colour::Yellow,
+                (None, None) => Colour::Green,
+            };
+            let threshold = match (osd.threshold, osd.current) {
+                (Some(Threshold::Nearfull), _) => ", nearfull",
+                (Some(Threshold::Backfillfull), _) => ", backfillfull",
+                (Some(Threshold::Full), _) => ", full",
+                (None, Some(Threshold::Nearfull)) => ", already nearfull",
+                (None, Some(Threshold::Backfillfull)) => ", already backfillfull",
+                (None, Some(Threshold::Full)) => ", already full",
+                (None, None) => "",
+            };
+            println!(
+                "{} {}: {:.1}% -> {:.1}% (+{} KiB{})",
+                colour.paint("●"),
+                osd.osd,
+                osd.utilisation * 100.0,
+                osd.projected * 100.0,
+                osd.kb_added,
+                threshold
+            );
+        }
+        let colour = match self.status {
+            Status::Safe => Colour::Green,
+            Status::Unknown => Colour::Yellow,
+            Status::NonSafe => Colour::Red,
+        };
+        let crossing = self
+            .projected
+            .iter()
+            .filter(|osd| osd.threshold.is_some())
+            .count();
+        println!(
+            "{} {}: {} OSDs would cross nearfull, backfillfull or full",
+            colour.paint("●"),
+            self.status,
+            crossing
+        );
+        let already: Vec<String> = self
+            .projected
+            .iter()
+            .filter(|osd| osd.threshold.is_none() && osd.current.is_some())
+            .map(|osd| osd.osd.to_string())
+            .collect();
+        if !already.is_empty() {
+            println!(
+                "{} OSDs {} are already nearfull or worse",
+                Colour::Yellow.paint("●"),
+                already.join(", ")
+            );
+        }
+    }
+}
+
+// Ratios in pre-Luminous PG maps are numbers, or strings in Firefly
+fn ratio_value(value: &Option<Value>) -> Option<f64> {
+    let ratio = match value {
+        Some(Value::Number(number)) => number.as_f64(),
+        Some(Value::String(string)) => string.parse().ok(),
+        _ => None,
+    };
+    // Luminous still reports them in the PG map, as zeroes
+    ratio.filter(|ratio| *ratio > 0.0)
+}
+
+impl DiagMap {
+    // Bytes one replica, or one shard for erasure coded pools, of a PG
+    // holding `bytes` of data takes up
+    pub(crate) fn shard_bytes(&self, pool: &Pools, bytes: u64) -> u64 {
+        if !pool.is_erasure() {
+            return bytes;
+        }
+        match self
+            .ec_profiles
+            .get(&pool.erasure_code_profile)
+            .and_then(|profile| profile.k())
+        {
+            Some(k) if k > 0 => bytes / k as u64,
+            _ => bytes,
+        }
+    }
+
+    // `nearfull`, `backfillfull` and `full` ratios, from the OSD map since
+    // Luminous and from the PG map before that
+    fn full_ratios(&self) -> (f64, f64, f64) {
+        let nearfull = self
+            .osd_map
+            .nearfull_ratio
+            .or_else(|| ratio_value(&self.pg_map.near_full_ratio))
+            .unwrap_or(NEARFULL_RATIO);
+        let full = self
+            .osd_map
+            .full_ratio
+            .or_else(|| ratio_value(&self.pg_map.full_ratio))
+            .unwrap_or(FULL_RATIO);
+        let backfillfull = self.osd_map.backfillfull_ratio.unwrap_or(BACKFILLFULL_RATIO);
+        (nearfull, backfillfull, full)
+    }
+
+    // The highest of the `ratios` `utilisation` is at or past
+    fn threshold(utilisation: f64, ratios: (f64, f64, f64)) -> Option<Threshold> {
+        let (nearfull_ratio, backfillfull_ratio, full_ratio) = ratios;
+        if utilisation >= full_ratio {
+            Some(Threshold::Full)
+        } else if utilisation >= backfillfull_ratio {
+            Some(Threshold::Backfillfull)
+        } else if utilisation >= nearfull_ratio {
+            Some(Threshold::Nearfull)
+        } else {
+            None
+        }
+    }
+
+    // Project the utilisation of every other OSD once the OSDs in `osd_ids`
+    // are weighted out and their PGs are backfilled to where CRUSH maps them.
+    // Space is only ever added, the copies that move away are kept until the
+    // backfill finishes. Removal is unsafe when the backfill would push any
+    // OSD past nearfull or a higher ratio. OSDs that are already past one are
+    // only held against the removal if they would cross the next.
+    pub fn project_capacity(&self, osd_ids: &[i32]) -> Result<CapacityProjection, CSDError> {
+        self.check_osds(osd_ids)?;
+        let crush_map = self.crush_map()?;
+
+        let mut added: BTreeMap<i32, u64> = BTreeMap::new();
+        for remap in self.remap_pgs(crush_map, osd_ids)? {
+            let bytes = self.shard_bytes(remap.pool, remap.pg_stat.stat_sum.num_bytes);
+            for osd in remap.targets() {
+                *added.entry(osd).or_insert(0) += bytes;
+            }
+        }
+
+        let ratios = self.full_ratios();
+        let (nearfull_ratio, backfillfull_ratio, full_ratio) = ratios;
+        let mut projected = Vec::new();
+        for osd_stat in &self.pg_map.osd_stats {
+            if osd_ids.contains(&osd_stat.osd) || osd_stat.kb == 0 {
+                continue;
+            }
+            let kb = osd_stat.kb;
+            let kb_used = osd_stat.kb_used;
+            let kb_added = added.get(&osd_stat.osd).map_or(0, |bytes| bytes / 1024);
+            let utilisation = kb_used as f64 / kb as f64;
+            let projection = (kb_used + kb_added) as f64 / kb as f64;
+            let current = DiagMap::threshold(utilisation, ratios);
+            let threshold =
+                DiagMap::threshold(projection, ratios).filter(|reached| Some(*reached) > current);
+            projected.push(OsdCapacity {
+                osd: osd_stat.osd,
+                kb,
+                kb_used,
+                kb_added,
+                utilisation,
+                projected: projection,
+                threshold,
+                current,
+            });
+        }
+        projected.sort_by(|a, b| b.projected.total_cmp(&a.projected));
+
+        let status = if projected.iter().any(|osd| osd.threshold.is_some()) {
+            Status::NonSafe
+        } else {
+            Status::Safe
+        };
+        Ok(CapacityProjection {
+            osds: osd_ids.to_vec(),
+            status,
+            nearfull_ratio,
+            backfillfull_ratio,
+            full_ratio,
+            projected,
+        })
+    }
+
+    // Print the result of `project_capacity` based on `format`
+    pub fn capacity_diag(self, osd_ids: &[i32], format: Format) -> Result<Status, CSDError> {
+        let projection = self.project_capacity(osd_ids)?;
+        projection.print(format);
+        Ok(projection.status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crushmap::CrushMap;
+    use crate::from::FromFile;
+    use crate::osdmap::{ErasureCodeProfile, OsdMap};
+    use crate::pgmap::PGMap;
+
+    fn luminous_racks(pg_dump: &str) -> DiagMap {
+        let mut ec_profiles = BTreeMap::new();
+        ec_profiles.insert(
+            "ec22".to_string(),
+            ErasureCodeProfile::from_file("test/luminous/erasure_code_profile_ec22.json").unwrap(),
+        );
+        DiagMap {
+            pg_map: PGMap::from_file(pg_dump).unwrap(),
+            osd_map: OsdMap::from_file("test/luminous/osd_dump_racks.json").unwrap(),
+            crush_map: Ok(CrushMap::from_file("test/luminous/osd_crush_dump_racks.json").unwrap()),
+            ec_profiles,
+        }
+    }
+
+    #[test]
+    fn capacity_luminous_room_to_spare() {
+        let projection = luminous_racks("test/luminous/pg_dump_racks.json")
+            .project_capacity(&[5])
+            .unwrap();
+        assert_eq!(projection.status, Status::Safe);
+        assert_eq!(projection.projected.len(), 7);
+        assert_eq!(projection.backfillfull_ratio, 0.9);
+        // The PGs on osd.5 land somewhere else
+        assert!(projection.projected.iter().any(|osd| osd.kb_added > 0));
+        assert!(projection
+            .projected
+            .iter()
+            .all(|osd| osd.projected >= osd.utilisation));
+    }
+
+    #[test]
+    fn capacity_luminous_nearfull() {
+        let projection = luminous_racks("test/luminous/pg_dump_racks_nearfull.json")
+            .project_capacity(&[5])
+            .unwrap();
+        assert_eq!(projection.status, Status::NonSafe);
+        // osd.4 is the only other OSD on stor-03 and takes most of osd.5's
+        // PGs, which pushes it past backfillfull
+        let fullest = &projection.projected[0];
+        assert_eq!(fullest.osd, 4);
+        assert!(fullest.projected >= projection.backfillfull_ratio);
+        assert_eq!(fullest.threshold, Some(Threshold::Backfillfull));
+        // OSDs nothing moves to stay below nearfull
+        assert!(projection
+            .projected
+            .iter()
+            .filter(|osd| osd.kb_added == 0)
+            .all(|osd| osd.threshold.is_none()));
+    }
+
+    #[test]
+    fn capacity_already_nearfull() {
+        let mut diag_map = luminous_racks("test/luminous/pg_dump_racks_nearfull.json");
+        let untouched = diag_map
+            .project_capacity(&[5])
+            .unwrap()
+            .projected
+            .iter()
+            .find(|osd| osd.kb_added == 0)
+            .unwrap()
+            .osd;
+        // Past nearfull before the removal, and nothing moves to it
+        for osd_stat in &mut diag_map.pg_map.osd_stats {
+            if osd_stat.osd == untouched {
+                osd_stat.kb_used = osd_stat.kb * 88 / 100;
+            }
+        }
+        let projection = diag_map.project_capacity(&[5]).unwrap();
+        let osd = projection.projected.iter().find(|osd| osd.osd == untouched).unwrap();
+        assert_eq!(osd.current, Some(Threshold::Nearfull));
+        assert_eq!(osd.threshold, None);
+        // osd.4 still crosses backfillfull
+        assert_eq!(projection.status, Status::NonSafe);
+        let projection = diag_map.project_capacity(&[]).unwrap();
+        assert_eq!(projection.status, Status::Safe);
+    }
+
+    #[test]
+    fn capacity_no_removal() {
+        let projection = luminous_racks("test/luminous/pg_dump_racks_nearfull.json")
+            .project_capacity(&[])
+            .unwrap();
+        assert_eq!(projection.status, Status::Safe);
+        assert!(projection.projected.iter().all(|osd| osd.kb_added == 0));
+    }
+
+    #[test]
+    fn capacity_jewel_ratios() {
+        let diag_map = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        };
+        assert_eq!(diag_map.full_ratios(), (0.85, 0.90, 0.95));
+        assert!(ratio_value(&Some(Value::String("0.850000".to_string()))).is_some());
+    }
+}
@@ -0,0 +1,209 @@
+// The lookup tables of ceph's fixed point `crush_ln`, see
+// `src/crush/crush_ln_table.h` in ceph's source, built from the definitions
+// given there:
+//
+//   RH_LH_TBL[2 * k] = 2^48 / (1.0 + k / 128.0)
+//   RH_LH_TBL[2 * k + 1] = 2^48 * log2(1.0 + k / 128.0)
+//   LL_TBL[k] = 2^48 * log2(1.0 + k / 2^15)
+//
+// Reciprocals are rounded up, so a normalized input times its reciprocal
+// never falls short of the next table step, and logarithms are rounded down.
+
+pub(crate) const RH_LH_TBL: [i64; 258] = [
+    0x0001000000000000, 0x0000000000000000,
+    0x0000fe03f80fe040, 0x000002dfca16dde1,
+    0x0000fc0fc0fc0fc1, 0x000005b9e5a170b4,
+    0x0000fa232cf25214, 0x0000088e68ea899a,
+    0x0000f83e0f83e0f9, 0x00000b5d69bac77e,
+    0x0000f6603d980f67, 0x00000e26fd5c8555,
+    0x0000f4898d5f85bc, 0x000010eb389fa29f,
+    0x0000f2b9d6480f2c, 0x000013aa2fdd27f1,
+    0x0000f0f0f0f0f0f1, 0x00001663f6fac913,
+    0x0000ef2eb71fc435, 0x00001918a16e4633,
+    0x0000ed7303b5cc0f, 0x00001bc84240adab,
+    0x0000ebbdb2a5c162, 0x00001e72ec117fa5,
+    0x0000ea0ea0ea0ea1, 0x00002118b119b4f3,
+    0x0000e865ac7b7604, 0x000023b9a32eaa56,
+    0x0000e6c2b4481cd9, 0x00002655d3c4f15c,
+    0x0000e525982af70d, 0x000028ed53f307ee,
+    0x0000e38e38e38e39, 0x00002b803473f7ad,
+    0x0000e1fc780e1fc8, 0x00002e0e85a9de04,
+    0x0000e070381c0e08, 0x0000309857a05e07,
+    0x0000dee95c4ca038, 0x0000331dba0efce1,
+    0x0000dd67c8a60dd7, 0x0000359ebc5b69d9,
+    0x0000dbeb61eed19d, 0x0000381b6d9bb29b,
+    0x0000da740da740db, 0x00003a93dc9864b2,
+    0x0000d901b2036407, 0x00003d0817ce9cd4,
+    0x0000d79435e50d7a, 0x00003f782d7204d0,
+    0x0000d62b80d62b81, 0x000041e42b6ec0c0,
+    0x0000d4c77b03531e, 0x0000444c1f6b4c2d,
+    0x0000d3680d3680d4, 0x000046b016ca47c1,
+    0x0000d20d20d20d21, 0x000049101eac381c,
+    0x0000d0b69fcbd259, 0x00004b6c43f1366a,
+    0x0000cf6474a8819f, 0x00004dc4933a9337,
+    0x0000ce168a772509, 0x0000501918ec6c11,
+    0x0000cccccccccccd, 0x00005269e12f346e,
+    0x0000cb8727c065c4, 0x000054b6f7f1325a,
+    0x0000ca4587e6b750, 0x0000570068e7ef5a,
+    0x0000c907da4e8712, 0x000059463f919dee,
+    0x0000c7ce0c7ce0c8, 0x00005b8887367433,
+    0x0000c6980c6980c7, 0x00005dc74ae9fbec,
+    0x0000c565c87b5f9e, 0x00006002958c5871,
+    0x0000c4372f855d83, 0x0000623a71cb82c8,
+    0x0000c30c30c30c31, 0x0000646eea247c5c,
+    0x0000c1e4bbd595f7, 0x000066a008e4788c,
+    0x0000c0c0c0c0c0c1, 0x000068cdd829fd81,
+    0x0000bfa02fe80bfb, 0x00006af861e5fc7d,
+    0x0000be82fa0be830, 0x00006d1fafdce20a,
+    0x0000bd6910470767, 0x00006f43cba79e40,
+    0x0000bc52640bc527, 0x00007164beb4a56d,
+    0x0000bb3ee721a54e, 0x000073829248e961,
+    0x0000ba2e8ba2e8bb, 0x0000759d4f80cba8,
+    0x0000b92143fa36f6, 0x000077b4ff5108d9,
+    0x0000b81702e05c0c, 0x000079c9aa879d53,
+    0x0000b70fbb5a19bf, 0x00007bdb59cca388,
+    0x0000b60b60b60b61, 0x00007dea15a32c1b,
+    0x0000b509e68a9b95, 0x00007ff5e66a0ffe,
+    0x0000b40b40b40b41, 0x000081fed45cbccb,
+    0x0000b30f63528918, 0x00008404e793fb81,
+    0x0000b21642c8590c, 0x000086082806b1d5,
+    0x0000b11fd3b80b12, 0x000088089d8a9e47,
+    0x0000b02c0b02c0b1, 0x00008a064fd50f2a,
+    0x0000af3addc680b0, 0x00008c01467b94bb,
+    0x0000ae4c415c9883, 0x00008df988f4ae80,
+    0x0000ad602b580ad7, 0x00008fef1e987409,
+    0x0000ac7691840ac8, 0x000091e20ea1393e,
+    0x0000ab8f69e2835a, 0x000093d2602c2e5f,
+    0x0000aaaaaaaaaaab, 0x000095c01a39fbd6,
+    0x0000a9c84a47a080, 0x000097ab43af59f9,
+    0x0000a8e83f5717c1, 0x00009993e355a4e5,
+    0x0000a80a80a80a81, 0x00009b79ffdb6c8b,
+    0x0000a72f0539782a, 0x00009d5d9fd5010b,
+    0x0000a655c4392d7c, 0x00009f3ec9bcfb80,
+    0x0000a57eb50295fb, 0x0000a11d83f4c355,
+    0x0000a4a9cf1d9684, 0x0000a2f9d4c51039,
+    0x0000a3d70a3d70a4, 0x0000a4d3c25e68dc,
+    0x0000a3065e3fae7d, 0x0000a6ab52d99e76,
+    0x0000a237c32b16d0, 0x0000a8808c384547,
+    0x0000a16b312ea8fd, 0x0000aa5374652a1c,
+    0x0000a0a0a0a0a0a1, 0x0000ac241134c4e9,
+    0x00009fd809fd80a0, 0x0000adf26865a8a1,
+    0x00009f1165e72549, 0x0000afbe7fa0f04d,
+    0x00009e4cad23dd60, 0x0000b1885c7aa982,
+    0x00009d89d89d89d9, 0x0000b35004723c46,
+    0x00009cc8e160c3fc, 0x0000b5157cf2d078,
+    0x00009c09c09c09c1, 0x0000b6d8cb53b0ca,
+    0x00009b4c6f9ef03b, 0x0000b899f4d8ab63,
+    0x00009a90e7d95bc7, 0x0000ba58feb2703a,
+    0x000099d722dabde6, 0x0000bc15edfeed32,
+    0x0000991f1a515886, 0x0000bdd0c7c9a817,
+    0x00009868c809868d, 0x0000bf89910c1678,
+    0x000097b425ed097c, 0x0000c1404eadf383,
+    0x000097012e025c05, 0x0000c2f5058593d9,
+    0x0000964fda6c0965, 0x0000c4a7ba58377c,
+    0x000095a02568095b, 0x0000c65871da59dd,
+    0x000094f2094f2095, 0x0000c80730b00016,
+    0x0000944580944581, 0x0000c9b3fb6d0559,
+    0x0000939a85c4093a, 0x0000cb5ed69565af,
+    0x000092f113840498, 0x0000cd07c69d8702,
+    0x0000924924924925, 0x0000ceaecfea8085,
+    0x000091a2b3c4d5e7, 0x0000d053f6d26089,
+    0x000090fdbc090fdc, 0x0000d1f73f9c70c0,
+    0x0000905a38633e07, 0x0000d398ae817906,
+    0x00008fb823ee08fc, 0x0000d53847ac00a6,
+    0x00008f1779d9fdc4, 0x0000d6d60f388e41,
+    0x00008e78356d1409, 0x0000d8720935e643,
+    0x00008dda5202376a, 0x0000da0c39a54804,
+    0x00008d3dcb08d3dd, 0x0000dba4a47aa996,
+    0x00008ca29c046515, 0x0000dd3b4d9cf24b,
+    0x00008c08c08c08c1, 0x0000ded038e633f3,
+    0x00008b70344a139c, 0x0000e0636a23e2ee,
+    0x00008ad8f2fba939, 0x0000e1f4e5170d02,
+    0x00008a42f870566a, 0x0000e384ad748f0e,
+    0x000089ae4089ae41, 0x0000e512c6e54998,
+    0x0000891ac73ae982, 0x0000e69f35065448,
+    0x0000888888888889, 0x0000e829fb693044,
+    0x000087f78087f781, 0x0000e9b31d93f98e,
+    0x00008767ab5f34e5, 0x0000eb3a9f019750,
+    0x000086d905447a35, 0x0000ecc08321eb30,
+    0x0000864b8a7de6d2, 0x0000ee44cd59ffab,
+    0x000085bf37612cef, 0x0000efc781043579,
+    0x0000853408534086, 0x0000f148a170700a,
+    0x000084a9f9c8084b, 0x0000f2c831e44116,
+    0x0000842108421085, 0x0000f446359b1353,
+    0x0000839930523fbf, 0x0000f5c2afc65447,
+    0x000083126e978d50, 0x0000f73da38d9d4a,
+    0x0000828cbfbeb9a1, 0x0000f8b7140edbb1,
+    0x0000820820820821, 0x0000fa2f045e7832,
+    0x000081848da8faf1, 0x0000fba577877d7d,
+    0x0000810204081021, 0x0000fd1a708bbe11,
+    0x0000808080808081, 0x0000fe8df263f957,
+    0x0000800000000000, 0x0001000000000000,
+];
+
+pub(crate) const LL_TBL: [u64; 256] = [
+    0x0000000000000000, 0x00000002e2a60a00, 0x00000005c5464ec5, 0x00000008a7e0ce67,
+    0x0000000b8a7588fd, 0x0000000e6d047e9c, 0x000000114f8daf5e, 0x0000001432111b58,
+    0x00000017148ec2a1, 0x00000019f706a552, 0x0000001cd978c380, 0x0000001fbbe51d43,
+    0x000000229e4bb2b2, 0x0000002580ac83e4, 0x00000028630790f0, 0x0000002b455cd9ed,
+    0x0000002e27ac5ef2, 0x0000003109f62017, 0x00000033ec3a1d71, 0x00000036ce78571a,
+    0x00000039b0b0cd26, 0x0000003c92e37fae, 0x0000003f75106ec8, 0x0000004257379a8c,
+    0x0000004539590310, 0x000000481b74a86c, 0x0000004afd8a8ab6, 0x0000004ddf9aaa06,
+    0x00000050c1a50672, 0x00000053a3a9a013, 0x0000005685a876fd, 0x0000005967a18b4a,
+    0x0000005c4994dd0f, 0x0000005f2b826c64, 0x000000620d6a3960, 0x00000064ef4c441a,
+    0x00000067d1288ca8, 0x0000006ab2ff1322, 0x0000006d94cfd79f, 0x00000070769ada35,
+    0x0000007358601afd, 0x000000763a1f9a0c, 0x000000791bd9577a, 0x0000007bfd8d535e,
+    0x0000007edf3b8dce, 0x00000081c0e406e3, 0x00000084a286beb2, 0x000000878423b552,
+    0x0000008a65baeadc, 0x0000008d474c5f65, 0x0000009028d81305, 0x000000930a5e05d3,
+    0x00000095ebde37e5, 0x00000098cd58a953, 0x0000009baecd5a33, 0x0000009e903c4a9d,
+    0x000000a171a57aa8, 0x000000a45308ea6a, 0x000000a7346699fb, 0x000000aa15be8970,
+    0x000000acf710b8e3, 0x000000afd85d2869, 0x000000b2b9a3d818, 0x000000b59ae4c80a,
+    0x000000b87c1ff853, 0x000000bb5d55690c, 0x000000be3e851a4a, 0x000000c11faf0c26,
+    0x000000c400d33eb6, 0x000000c6e1f1b211, 0x000000c9c30a664d, 0x000000cca41d5b82,
+    0x000000cf852a91c8, 0x000000d266320933, 0x000000d54733c1dd, 0x000000d8282fbbdb,
+    0x000000db0925f744, 0x000000ddea167430, 0x000000e0cb0132b5, 0x000000e3abe632ea,
+    0x000000e68cc574e6, 0x000000e96d9ef8c1, 0x000000ec4e72be90, 0x000000ef2f40c66c,
+    0x000000f21009106a, 0x000000f4f0cb9ca2, 0x000000f7d1886b2a, 0x000000fab23f7c1a,
+    0x000000fd92f0cf88, 0x00000100739c658c, 0x0000010354423e3c, 0x0000010634e259af,
+    0x00000109157cb7fc, 0x0000010bf611593a, 0x0000010ed6a03d7f, 0x00000111b72964e4,
+    0x0000011497accf7e, 0x00000117782a7d64, 0x0000011a58a26ead, 0x0000011d3914a371,
+    0x0000012019811bc6, 0x00000122f9e7d7c3, 0x00000125da48d77f, 0x00000128baa41b10,
+    0x0000012b9af9a28e, 0x0000012e7b496e0f, 0x000001315b937daa, 0x000001343bd7d177,
+    0x000001371c16698c, 0x00000139fc4f45ff, 0x0000013cdc8266e9, 0x0000013fbcafcc5e,
+    0x000001429cd77678, 0x000001457cf9654b, 0x000001485d1598f0, 0x0000014b3d2c117c,
+    0x0000014e1d3ccf08, 0x00000150fd47d1a9, 0x00000153dd4d1976, 0x00000156bd4ca687,
+    0x000001599d4678f2, 0x0000015c7d3a90ce, 0x0000015f5d28ee31, 0x000001623d119134,
+    0x000001651cf479ec, 0x00000167fcd1a870, 0x0000016adca91cd7, 0x0000016dbc7ad738,
+    0x000001709c46d7aa, 0x000001737c0d1e44, 0x000001765bcdab1c, 0x000001793b887e49,
+    0x0000017c1b3d97e2, 0x0000017efaecf7fe, 0x00000181da969eb3, 0x00000184ba3a8c19,
+    0x0000018799d8c046, 0x0000018a79713b52, 0x0000018d5903fd52, 0x000001903891065d,
+    0x000001931818568b, 0x00000195f799edf2, 0x00000198d715ccaa, 0x0000019bb68bf2c8,
+    0x0000019e95fc6063, 0x000001a175671593, 0x000001a454cc126e, 0x000001a7342b570b,
+    0x000001aa1384e380, 0x000001acf2d8b7e5, 0x000001afd226d450, 0x000001b2b16f38d9,
+    0x000001b590b1e595, 0x000001b86feeda9b, 0x000001bb4f261803, 0x000001be2e579de3,
+    0x000001c10d836c51, 0x000001c3eca98365, 0x000001c6cbc9e336, 0x000001c9aae48bd9,
+    0x000001cc89f97d67, 0x000001cf6908b7f5, 0x000001d248123b9a, 0x000001d52716086d,
+    0x000001d806141e86, 0x000001dae50c7df9, 0x000001ddc3ff26df, 0x000001e0a2ec194e,
+    0x000001e381d3555d, 0x000001e660b4db23, 0x000001e93f90aab5, 0x000001ec1e66c42b,
+    0x000001eefd37279d, 0x000001f1dc01d51f, 0x000001f4bac6ccca, 0x000001f799860eb3,
+    0x000001fa783f9af3, 0x000001fd56f3719e, 0x0000020035a192cc, 0x000002031449fe94,
+    0x00000205f2ecb50d, 0x00000208d189b64d, 0x0000020bb021026a, 0x0000020e8eb2997c,
+    0x000002116d3e7b99, 0x000002144bc4a8d8, 0x000002172a452150, 0x0000021a08bfe517,
+    0x0000021ce734f444, 0x0000021fc5a44eee, 0x00000222a40df52c, 0x000002258271e713,
+    0x0000022860d024bb, 0x0000022b3f28ae3b, 0x0000022e1d7b83a8, 0x00000230fbc8a51b,
+    0x00000233da1012a9, 0x00000236b851cc69, 0x00000239968dd272, 0x0000023c74c424db,
+    0x0000023f52f4c3ba, 0x00000242311faf25, 0x000002450f44e735, 0x00000247ed646bfe,
+    0x0000024acb7e3d98, 0x0000024da9925c1a, 0x0000025087a0c799, 0x0000025365a9802e,
+    0x0000025643ac85ee, 0x0000025921a9d8f0, 0x0000025bffa1794b, 0x0000025edd936716,
+    0x00000261bb7fa266, 0x0000026499662b53, 0x00000267774701f3, 0x0000026a5522265e,
+    0x0000026d32f798a9, 0x0000027010c758eb, 0x00000272ee91673b, 0x00000275cc55c3b0,
+    0x00000278aa146e5f, 0x0000027b87cd6761, 0x0000027e6580aecb, 0x00000281432e44b3,
+    0x0000028420d62932, 0x00000286fe785c5c, 0x00000289dc14de4a, 0x0000028cb9abaf11,
+    0x0000028f973ccec8, 0x0000029274c83d86, 0x00000295524dfb61, 0x000002982fce086f,
+    0x0000029b0d4864c9, 0x0000029deabd1083, 0x000002a0c82c0bb5, 0x000002a3a5955676,
+    0x000002a682f8f0db, 0x000002a96056dafc, 0x000002ac3daf14ef, 0x000002af1b019eca,
+    0x000002b1f84e78a5, 0x000002b4d595a296, 0x000002b7b2d71cb3, 0x000002ba9012e713,
+    0x000002bd6d4901cc, 0x000002c04a796cf6, 0x000002c327a428a6, 0x000002c604c934f4,
+    0x000002c8e1e891f6, 0x000002cbbf023fc2, 0x000002ce9c163e6e, 0x000002d179248e13,
+    0x000002d4562d2ec6, 0x000002d73330209d, 0x000002da102d63b0, 0x000002dced24f814,
+];
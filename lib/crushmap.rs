@@ -0,0 +1,286 @@
+use crate::osdmap::Pools;
+
+// See `src/crush/CrushWrapper.cc` in ceph's source for `osd crush dump`
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct CrushMap {
+    pub devices: Vec<Device>,
+    pub types: Vec<BucketType>,
+    pub buckets: Vec<Bucket>,
+    pub rules: Vec<Rule>,
+    pub tunables: Tunables,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Device {
+    pub id: i32,
+    pub name: String,
+    // Device classes were added in Luminous
+    pub class: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct BucketType {
+    pub type_id: i32,
+    pub name: String,
+}
+
+// Weights are 16.16 fixed point, `0x10000` being a weight of 1.0
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Bucket {
+    pub id: i32,
+    pub name: String,
+    pub type_id: i32,
+    pub type_name: String,
+    pub weight: u32,
+    pub alg: String,
+    pub hash: String,
+    pub items: Vec<BucketItem>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct BucketItem {
+    pub id: i32,
+    pub weight: u32,
+    pub pos: i32,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Rule {
+    pub rule_id: i32,
+    pub rule_name: String,
+    // Dropped in Quincy, where rule ids and rulesets were merged
+    pub ruleset: Option<i32>,
+    #[serde(rename = "type")]
+    pub rule_type: i32,
+    pub min_size: Option<i32>,
+    pub max_size: Option<i32>,
+    pub steps: Vec<RuleStep>,
+}
+
+// `op` is one of `take`, `choose_firstn`, `chooseleaf_firstn`, `choose_indep`,
+// `chooseleaf_indep`, `emit` or one of the `set_*` tunable overrides
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct RuleStep {
+    pub op: String,
+    pub item: Option<i32>,
+    pub item_name: Option<String>,
+    pub num: Option<i32>,
+    #[serde(rename = "type")]
+    pub step_type: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Tunables {
+    pub choose_local_tries: u32,
+    pub choose_local_fallback_tries: u32,
+    pub choose_total_tries: u32,
+    pub chooseleaf_descend_once: u32,
+    pub chooseleaf_vary_r: u32,
+    pub chooseleaf_stable: Option<u32>,
+    pub straw_calc_version: u32,
+    pub allowed_bucket_algs: u32,
+    pub profile: String,
+    pub optimal_tunables: u32,
+    pub legacy_tunables: u32,
+    pub minimum_required_version: String,
+}
+
+impl Bucket {
+    // Luminous device classes add a shadow tree per class, named
+    // `<bucket>~<class>`, which holds the same OSDs as the real tree
+    pub fn is_shadow(&self) -> bool {
+        self.name.contains('~')
+    }
+}
+
+impl Rule {
+    // The bucket type replicas are spread across, i.e. the type of the last
+    // `choose*` step. For `chooseleaf` steps that is the type the leaf is
+    // chosen under, typically `host`.
+    pub fn failure_domain(&self) -> Option<&str> {
+        self.steps
+            .iter()
+            .rev()
+            .filter(|step| step.op.starts_with("choose"))
+            .find_map(|step| step.step_type.as_ref())
+            .map(|step_type| step_type.as_str())
+    }
+}
+
+impl CrushMap {
+    pub fn bucket(&self, id: i32) -> Option<&Bucket> {
+        self.buckets.iter().find(|bucket| bucket.id == id)
+    }
+
+    pub fn bucket_by_name(&self, name: &str) -> Option<&Bucket> {
+        self.buckets.iter().find(|bucket| bucket.name == name)
+    }
+
+    pub fn device(&self, osd_id: i32) -> Option<&Device> {
+        self.devices.iter().find(|device| device.id == osd_id)
+    }
+
+    pub fn device_class(&self, osd_id: i32) -> Option<&str> {
+        self.device(osd_id)
+            .and_then(|device| device.class.as_ref())
+            .map(|class| class.as_str())
+    }
+
+    // Pools refer to `crush_rule` since Luminous, older releases use the
+    // `crush_ruleset` which may differ from the rule id
+    pub fn rule(&self, rule_id: i32) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.rule_id == rule_id)
+    }
+
+    pub fn rule_by_ruleset(&self, ruleset: i32) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .find(|rule| rule.ruleset.unwrap_or(rule.rule_id) == ruleset)
+    }
+
+    pub fn pool_rule(&self, pool: &Pools) -> Option<&Rule> {
+        match (pool.crush_rule, pool.crush_ruleset) {
+            (Some(rule_id), _) => self.rule(rule_id),
+            (None, Some(ruleset)) => self.rule_by_ruleset(ruleset),
+            (None, None) => None,
+        }
+    }
+
+    pub fn failure_domain(&self, rule_id: i32) -> Option<&str> {
+        self.rule(rule_id).and_then(|rule| rule.failure_domain())
+    }
+
+    // The bucket directly holding `item`, ignoring device class shadow trees
+    pub fn parent(&self, item: i32) -> Option<&Bucket> {
+        self.buckets.iter().find(|bucket| {
+            !bucket.is_shadow() && bucket.items.iter().any(|child| child.id == item)
+        })
+    }
+
+    // Walk up from `item` to the first bucket of type `type_name`
+    pub fn ancestor(&self, item: i32, type_name: &str) -> Option<&Bucket> {
+        let mut parent = self.parent(item);
+        while let Some(bucket) = parent {
+            if bucket.type_name == type_name {
+                return Some(bucket);
+            }
+            parent = self.parent(bucket.id);
+        }
+        None
+    }
+
+    pub fn host(&self, osd_id: i32) -> Option<&Bucket> {
+        self.ancestor(osd_id, "host")
+    }
+
+    // Every bucket of type `type_name` in the tree below the bucket `id`
+    pub fn buckets_under(&self, id: i32, type_name: &str) -> Vec<&Bucket> {
+        let mut buckets = Vec::new();
+        let mut stack = vec![id];
+        while let Some(item) = stack.pop() {
+            if let Some(bucket) = self.bucket(item) {
+                if bucket.type_name == type_name {
+                    buckets.push(bucket);
+                } else {
+                    stack.extend(bucket.items.iter().map(|child| child.id));
+                }
+            }
+        }
+        buckets
+    }
+
+    // Every OSD in the tree below the bucket `id`
+    pub fn osds_under(&self, id: i32) -> Vec<i32> {
+        let mut osds = Vec::new();
+        let mut stack = vec![id];
+        while let Some(item) = stack.pop() {
+            if item >= 0 {
+                osds.push(item);
+            } else if let Some(bucket) = self.bucket(item) {
+                stack.extend(bucket.items.iter().map(|child| child.id));
+            }
+        }
+        osds.sort_unstable();
+        osds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CrushMap;
+    use crate::from::FromFile;
+
+    // Luminous tests
+    #[test]
+    fn crushmap_from_luminous_file() {
+        let crushmap = CrushMap::from_file("test/luminous/osd_crush_dump.json").unwrap();
+        assert_eq!(crushmap.devices.len(), 3);
+        assert_eq!(crushmap.device_class(1), Some("hdd"));
+        assert_eq!(crushmap.tunables.chooseleaf_stable, Some(1));
+    }
+
+    #[test]
+    fn crushmap_luminous_host() {
+        let crushmap = CrushMap::from_file("test/luminous/osd_crush_dump.json").unwrap();
+        // The shadow `node2~hdd` bucket also holds osd.1
+        assert_eq!(crushmap.host(1).unwrap().name, "node2");
+        assert_eq!(crushmap.ancestor(1, "root").unwrap().name, "default");
+        assert!(crushmap.ancestor(1, "rack").is_none());
+    }
+
+    #[test]
+    fn crushmap_luminous_failure_domain() {
+        let crushmap = CrushMap::from_file("test/luminous/osd_crush_dump.json").unwrap();
+        assert_eq!(crushmap.failure_domain(0), Some("host"));
+        assert_eq!(crushmap.failure_domain(5), None);
+    }
+
+    #[test]
+    fn crushmap_luminous_osds_under() {
+        let crushmap = CrushMap::from_file("test/luminous/osd_crush_dump.json").unwrap();
+        let root = crushmap.bucket_by_name("default").unwrap();
+        assert_eq!(crushmap.osds_under(root.id), vec![0, 1, 2]);
+        let host = crushmap.bucket_by_name("node3").unwrap();
+        assert_eq!(crushmap.osds_under(host.id), vec![2]);
+    }
+
+    #[test]
+    fn crushmap_luminous_buckets_under() {
+        let crushmap = CrushMap::from_file("test/luminous/osd_crush_dump_racks.json").unwrap();
+        assert_eq!(crushmap.buckets_under(-1, "host").len(), 4);
+        assert_eq!(crushmap.buckets_under(-1, "rack").len(), 2);
+        let rack = crushmap.bucket_by_name("rack2").unwrap();
+        let hosts: Vec<&str> = crushmap
+            .buckets_under(rack.id, "host")
+            .iter()
+            .map(|host| host.name.as_str())
+            .collect();
+        assert_eq!(hosts, vec!["stor-04", "stor-03"]);
+    }
+
+    #[test]
+    fn crushmap_luminous_pool_rule() {
+        use crate::osdmap::OsdMap;
+        let crushmap = CrushMap::from_file("test/luminous/osd_crush_dump.json").unwrap();
+        let osdmap = OsdMap::from_file("test/luminous/osd_dump_non_safe.json").unwrap();
+        let rule = crushmap.pool_rule(&osdmap.pools[0]).unwrap();
+        assert_eq!(rule.rule_name, "replicated_rule");
+    }
+
+    // Jewel tests
+    #[test]
+    fn crushmap_from_jewel_file() {
+        let crushmap = CrushMap::from_file("test/jewel/osd_crush_dump.json").unwrap();
+        assert_eq!(crushmap.device_class(0), None);
+        assert_eq!(crushmap.rule_by_ruleset(0).unwrap().rule_name, "replicated_ruleset");
+        assert_eq!(crushmap.host(0).unwrap().name, "node1");
+    }
+
+    #[test]
+    #[should_panic]
+    fn crushmap_from_ceph_panic() {
+        use crate::from::FromCeph;
+        let crushmap = CrushMap::from_ceph("osd crush dump");
+        assert!(crushmap.is_ok());
+    }
+}
@@ -1,11 +1,13 @@
 use ansi_term::Colour;
-use crate::pgmap::PGMap;
-use crate::osdmap::OsdMap;
+use crate::backend::{CephBackend, CliBackend};
+use crate::crushmap::CrushMap;
+use crate::pgmap::{PGMap, PgStats};
+use crate::osdmap::{ErasureCodeProfile, OsdMap, Osds, Pools};
 use crate::pgstate::RmSafety;
 use crate::error::CSDError;
 use crate::from::FromCeph;
 
-use std::collections::BinaryHeap;
+use std::collections::{BTreeMap, BinaryHeap};
 use std::fmt;
 
 // Format for printing
@@ -34,32 +36,102 @@ impl fmt::Display for Status {
     }
 }
 
-pub struct PgDiag {
-    osd_id: i32,
-    pg_info: PgInfo,
+// Why a PG got the removability it has
+#[derive(Serialize, Debug, Copy, Clone, Ord, Eq, PartialEq, PartialOrd)]
+pub enum SafetyRule {
+    // `active+clean`
+    Clean,
+    // A state such as `undersized` or `down`, see `RmSafety`
+    UnsafeState,
+    // Any other state, the PG might still recover
+    PendingState,
+    // Every member left in the acting set is needed to stay active
+    AtMinMembers,
 }
 
-impl PgDiag {
-    fn new(osd_id: i32, pg_info: PgInfo) -> PgDiag {
-        PgDiag { osd_id, pg_info }
+impl fmt::Display for SafetyRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SafetyRule::Clean => write!(f, "active+clean"),
+            SafetyRule::UnsafeState => write!(f, "unsafe PG state"),
+            SafetyRule::PendingState => write!(f, "PG state not settled"),
+            SafetyRule::AtMinMembers => write!(f, "no members to spare"),
+        }
     }
 }
 
 // Holds information about a PG's status, it's ID and state
-#[derive(Debug, Clone, Ord, Eq, PartialEq, PartialOrd)]
+#[derive(Serialize, Debug, Clone, Ord, Eq, PartialEq, PartialOrd)]
 pub struct PgInfo {
     pg_id: String,
     pg_state: String,
+    pool: String,
+    rule: SafetyRule,
+    #[serde(skip)]
     rm_safety: RmSafety,
 }
 
 impl PgInfo {
     fn new(states: &str, pgid: String) -> PgInfo {
+        let rm_safety = RmSafety::new(states);
         PgInfo {
             pg_id: pgid,
             pg_state: states.to_string(),
-            rm_safety: RmSafety::new(&states),
+            pool: String::new(),
+            rule: match rm_safety {
+                RmSafety::None => SafetyRule::UnsafeState,
+                RmSafety::Pending => SafetyRule::PendingState,
+                RmSafety::Total => SafetyRule::Clean,
+            },
+            rm_safety,
+        }
+    }
+
+    fn status(&self) -> Status {
+        match self.rm_safety {
+            RmSafety::None => Status::NonSafe,
+            RmSafety::Pending => Status::Unknown,
+            RmSafety::Total => Status::Safe,
+        }
+    }
+}
+
+// Where an OSD stands in the OSD map
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct OsdState {
+    pub up: bool,
+    #[serde(rename = "in")]
+    pub osd_in: bool,
+    pub exists: bool,
+    pub destroyed: bool,
+}
+
+impl OsdState {
+    fn new(osd: &Osds) -> OsdState {
+        OsdState {
+            up: osd.up != 0,
+            osd_in: osd.osd_in != 0,
+            exists: osd.exists(),
+            destroyed: osd.destroyed(),
+        }
+    }
+}
+
+impl fmt::Display for OsdState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.exists {
+            return write!(f, "does not exist");
+        }
+        write!(
+            f,
+            "{}, {}",
+            if self.up { "up" } else { "down" },
+            if self.osd_in { "in" } else { "out" }
+        )?;
+        if self.destroyed {
+            write!(f, ", destroyed")?;
         }
+        Ok(())
     }
 }
 
@@ -67,13 +139,125 @@ impl PgInfo {
 pub struct OsdDiag {
     osd_id: i32,
     osd_status: BinaryHeap<Status>,
+    // `None` for an OSD a PG maps to that is missing from the OSD map
+    state: Option<OsdState>,
+    // The PGs behind `osd_status`, kept for `--explain`
+    #[serde(skip)]
+    pgs: Vec<PgInfo>,
 }
 
 impl OsdDiag {
-    fn new(osd_id: i32) -> OsdDiag {
+    fn new(osd_id: i32, state: Option<OsdState>) -> OsdDiag {
         OsdDiag {
             osd_id,
             osd_status: BinaryHeap::new(),
+            state,
+            pgs: Vec::new(),
+        }
+    }
+
+    // Count `pg_info` towards the OSD's status
+    fn push(&mut self, pg_info: PgInfo) {
+        self.osd_status.push(pg_info.status());
+        self.pgs.push(pg_info);
+    }
+
+    // The OSD's status, with `empty` for an OSD no PG maps to, followed by
+    // its state, e.g. `Removable, empty (down, out)`
+    fn verdict(&self, status: Status) -> String {
+        let mut verdict = status.to_string();
+        if self.pgs.is_empty() {
+            verdict.push_str(", empty");
+        }
+        if let Some(state) = self.state {
+            verdict.push_str(&format!(" ({})", state));
+        }
+        verdict
+    }
+
+    // The PGs that gave the OSD its status
+    fn explain(&self) -> OsdExplain {
+        let status = self.osd_status.peek().cloned().unwrap_or(Status::Safe);
+        let mut pgs: Vec<PgInfo> = self
+            .pgs
+            .iter()
+            .filter(|pg| status != Status::Safe && pg.status() == status)
+            .cloned()
+            .collect();
+        pgs.sort_by(|a, b| a.rule.cmp(&b.rule).reverse().then(a.pg_id.cmp(&b.pg_id)));
+        OsdExplain {
+            osd_id: self.osd_id,
+            status,
+            state: self.state,
+            verdict: self.verdict(status),
+            pg_count: self.pgs.len(),
+            pgs,
+        }
+    }
+}
+
+// An OSD's status with the PGs that set it. Removable OSDs only carry a
+// count as every one of their PGs is `active+clean`.
+#[derive(Serialize, Debug)]
+pub struct OsdExplain {
+    osd_id: i32,
+    status: Status,
+    state: Option<OsdState>,
+    verdict: String,
+    pg_count: usize,
+    pgs: Vec<PgInfo>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClusterExplain {
+    pub(crate) osds: Vec<OsdExplain>,
+}
+
+impl ClusterExplain {
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => self.print_pretty(),
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+
+    fn print_pretty(&self) {
+        println!("Current OSD statuses:");
+        for osd in &self.osds {
+            let colour = match osd.status {
+                Status::Safe => Colour::Green,
+                Status::Unknown => Colour::Yellow,
+                Status::NonSafe => Colour::Red,
+            };
+            if osd.pg_count == 0 {
+                println!("{} {}: {}", colour.paint("●"), osd.osd_id, osd.verdict);
+                continue;
+            }
+            if osd.pgs.is_empty() {
+                println!(
+                    "{} {}: {}, {} PGs active+clean",
+                    colour.paint("●"),
+                    osd.osd_id,
+                    osd.verdict,
+                    osd.pg_count
+                );
+                continue;
+            }
+            println!(
+                "{} {}: {}, {} of {} PGs",
+                colour.paint("●"),
+                osd.osd_id,
+                osd.verdict,
+                osd.pgs.len(),
+                osd.pg_count
+            );
+            for pg in &osd.pgs {
+                println!("    {} ({}) {}: {}", pg.pg_id, pg.pool, pg.pg_state, pg.rule);
+            }
         }
     }
 }
@@ -89,6 +273,18 @@ pub struct ClusterReview {
     not_removable: Vec<i32>,
     #[serde(rename = "Pending")]
     pending: Vec<i32>,
+    // Every OSD with its status and state in the OSD map
+    #[serde(rename = "OSDs")]
+    osds: Vec<OsdReview>,
+}
+
+#[derive(Serialize)]
+struct OsdReview {
+    osd_id: i32,
+    status: Status,
+    // No PG maps to the OSD
+    empty: bool,
+    state: Option<OsdState>,
 }
 
 impl ClusterReview {
@@ -101,6 +297,12 @@ impl ClusterReview {
                     Status::Safe => review.removable.push(osd.osd_id),
                     Status::Unknown => review.pending.push(osd.osd_id),
                 }
+                review.osds.push(OsdReview {
+                    osd_id: osd.osd_id,
+                    status: *osd_status,
+                    empty: osd.pgs.is_empty(),
+                    state: osd.state,
+                });
             }
         }
         review
@@ -128,6 +330,18 @@ impl ClusterDiag {
         };
     }
 
+    // Restrict the diagnosis to `osd_ids`, which are all in the OSD map and
+    // so already have a status
+    fn retain_osds(&mut self, osd_ids: &[i32]) {
+        self.osd_diags.retain(|osd| osd_ids.contains(&osd.osd_id));
+    }
+
+    fn explain(&self) -> ClusterExplain {
+        ClusterExplain {
+            osds: self.osd_diags.iter().map(|osd| osd.explain()).collect(),
+        }
+    }
+
     fn status(&mut self) -> Status {
         for osd in &self.osd_diags {
             if let Some(osd_status) = osd.osd_status.peek() {
@@ -157,19 +371,19 @@ impl ClusterDiag {
                         "{} {}: {}",
                         Colour::Red.paint("●"),
                         osd.osd_id,
-                        osd_status
+                        osd.verdict(*osd_status)
                     ),
                     Status::Safe => println!(
                         "{} {}: {}",
                         Colour::Green.paint("●"),
                         osd.osd_id,
-                        osd_status
+                        osd.verdict(*osd_status)
                     ),
                     Status::Unknown => println!(
                         "{} {}: {}",
                         Colour::Yellow.paint("●"),
                         osd.osd_id,
-                        osd_status
+                        osd.verdict(*osd_status)
                     ),
                 }
             }
@@ -177,112 +391,402 @@ impl ClusterDiag {
     }
 
     fn print_json(&self) {
-        if let Ok(json) = serde_json::to_string(&ClusterReview::from_diag(&self)) {
+        if let Ok(json) = serde_json::to_string(&ClusterReview::from_diag(self)) {
             println!("{}", json);
         }
     }
 }
 
-#[derive(Debug, Clone)]
+// How much room the PGs of a pool have before losing another member makes
+// one of them inactive. `margin` is `None` for a pool without PGs, `pending`
+// whether a PG with that margin is not active+clean, so the margin may still
+// change as it settles.
+#[derive(Serialize, Debug, Clone)]
+pub struct PoolMargin {
+    pub pool: String,
+    pub pool_id: i32,
+    pub size: i32,
+    pub min_size: i32,
+    pub required: i32,
+    pub pgs: usize,
+    pub margin: Option<i32>,
+    pub pending: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct QuickReview {
+    #[serde(rename = "Safe to remove an OSD")]
+    pub safe: bool,
+    pub pools: Vec<PoolMargin>,
+}
+
+impl QuickReview {
+    // Safe only when every margin is at least one and none of them rests on
+    // a PG that is still settling
+    pub fn status(&self) -> Status {
+        if !self.safe {
+            Status::NonSafe
+        } else if self.pools.iter().any(|pool| pool.pending) {
+            Status::Unknown
+        } else {
+            Status::Safe
+        }
+    }
+
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => self.print_pretty(),
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+
+    fn print_pretty(&self) {
+        println!("Pool margins:");
+        for pool in &self.pools {
+            match pool.margin {
+                Some(margin) => println!(
+                    "{} {}: margin {} over {} required, {} PGs{}",
+                    if margin < 1 {
+                        Colour::Red.paint("●")
+                    } else if pool.pending {
+                        Colour::Yellow.paint("●")
+                    } else {
+                        Colour::Green.paint("●")
+                    },
+                    pool.pool,
+                    margin,
+                    pool.required,
+                    pool.pgs,
+                    if pool.pending { ", not active+clean yet" } else { "" }
+                ),
+                None => println!("{} {}: no PGs", Colour::Green.paint("●"), pool.pool),
+            }
+        }
+        match self.status() {
+            Status::Safe => println!("{} Safe to remove an OSD", Colour::Green.paint("●")),
+            Status::Unknown => println!(
+                "{} Pending, PGs are still settling",
+                Colour::Yellow.paint("●")
+            ),
+            Status::NonSafe => println!("{} Not safe to remove an OSD", Colour::Red.paint("●")),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct DiagMap {
-    pg_map: PGMap,
-    osd_map: OsdMap,
+    pub(crate) pg_map: PGMap,
+    pub(crate) osd_map: OsdMap,
+    // Only needed for checks that recompute placement, why it could not be
+    // fetched otherwise
+    pub(crate) crush_map: Result<CrushMap, CSDError>,
+    // Erasure code profiles of the erasure coded pools, by profile name
+    pub(crate) ec_profiles: BTreeMap<String, ErasureCodeProfile>,
 }
 
 impl DiagMap {
+    // Build the map with the `ceph` command line tool
     pub fn new() -> Result<DiagMap, CSDError> {
+        DiagMap::from_backend(&CliBackend)
+    }
+
+    // Build the map from whichever backend answers the mon commands. Only the
+    // checks that recompute placement need the CRUSH map, the others still
+    // run without it.
+    pub fn from_backend<B: CephBackend + ?Sized>(backend: &B) -> Result<DiagMap, CSDError> {
+        let osd_map = OsdMap::from_backend(backend, "osd dump")?;
+        let mut ec_profiles = BTreeMap::new();
+        for pool in osd_map.pools.iter().filter(|pool| pool.is_erasure()) {
+            if !ec_profiles.contains_key(&pool.erasure_code_profile) {
+                let profile = ErasureCodeProfile::from_backend(
+                    backend,
+                    &format!("osd erasure-code-profile get {}", pool.erasure_code_profile),
+                )?;
+                ec_profiles.insert(pool.erasure_code_profile.clone(), profile);
+            }
+        }
+        let pg_map = PGMap::from_backend(backend, "pg dump")?;
+        let crush_map = CrushMap::from_backend(backend, "osd crush dump");
+        if let Err(ref err) = crush_map {
+            warn!("Could not fetch the CRUSH map, {}", err);
+        }
         Ok(DiagMap {
-            pg_map: PGMap::from_ceph("pg dump")?,
-            osd_map: OsdMap::from_ceph("osd dump")?,
+            pg_map,
+            osd_map,
+            crush_map,
+            ec_profiles,
         })
     }
 
-    // Quick check to see if `min_size +1` is satisfied
-    pub fn quick_diag(self, format: Format) -> bool {
-        let mut safe: bool = false;
-        for stat in self.pg_map.pg_stats {
-            for pool in self.osd_map.pools.iter() {
-                if (stat.up.len() as i32) >= (pool.min_size + 1) {
-                    safe = true;
-                }
+    // The CRUSH map, for checks that recompute placement
+    pub(crate) fn crush_map(&self) -> Result<&CrushMap, CSDError> {
+        self.crush_map
+            .as_ref()
+            .map_err(|err| CSDError::CrushError(format!("no CRUSH map available, {}", err)))
+    }
+
+    // How many replicas, or shards for erasure coded pools, a PG of `pool`
+    // needs to stay active. An erasure coded PG needs `k` shards to rebuild
+    // its objects and `min_size` to accept I/O, whichever is more.
+    pub(crate) fn required_members(&self, pool: &Pools) -> i32 {
+        if !pool.is_erasure() {
+            return pool.min_size;
+        }
+        match self
+            .ec_profiles
+            .get(&pool.erasure_code_profile)
+            .and_then(|profile| profile.k())
+        {
+            Some(k) => k.max(pool.min_size),
+            None => {
+                warn!(
+                    "No erasure code profile {} for pool {}",
+                    pool.erasure_code_profile, pool.pool_name
+                );
+                pool.min_size
             }
         }
-        match format {
-            Format::Pretty => {
-                if safe {
-                    println!("{} Safe to remove an OSD", Colour::Green.paint("●"));
-                } else {
-                    println!("{} Not safe to remove an OSD", Colour::Red.paint("●"));
-                };
+    }
+
+    // Whether every member left in the PG's acting set is needed to keep it
+    // active. Empty shard slots count as redundancy that is already lost.
+    pub(crate) fn at_min_members(&self, pg_stat: &PgStats) -> bool {
+        match pg_stat.pool_id().and_then(|id| self.osd_map.pool(id)) {
+            Some(pool) => (pg_stat.acting_osds().count() as i32) <= self.required_members(pool),
+            None => false,
+        }
+    }
+
+    // Match every PG to its pool by the pgid prefix and find, per pool, the
+    // smallest number of `up` members any of its PGs has left over what the
+    // pool needs to stay active
+    pub fn quick_review(&self) -> QuickReview {
+        let mut pools: Vec<PoolMargin> = self
+            .osd_map
+            .pools
+            .iter()
+            .map(|pool| PoolMargin {
+                pool: pool.pool_name.clone(),
+                pool_id: pool.pool,
+                size: pool.size,
+                min_size: pool.min_size,
+                required: self.required_members(pool),
+                pgs: 0,
+                margin: None,
+                pending: false,
+            })
+            .collect();
+        let mut safe = true;
+        for stat in &self.pg_map.pg_stats {
+            let pool = match stat
+                .pool_id()
+                .and_then(|id| pools.iter_mut().find(|pool| pool.pool_id == id))
+            {
+                Some(pool) => pool,
+                None => {
+                    warn!("No pool found for PG {}", stat.pgid);
+                    safe = false;
+                    continue;
+                }
+            };
+            let margin = stat.up_osds().count() as i32 - pool.required;
+            let settled = RmSafety::new(&stat.state) == RmSafety::Total;
+            pool.pgs += 1;
+            match pool.margin {
+                Some(lowest) if lowest < margin => {}
+                Some(lowest) if lowest == margin => pool.pending |= !settled,
+                _ => {
+                    pool.margin = Some(margin);
+                    pool.pending = !settled;
+                }
             }
-            Format::Json => println!("{{\"Safe to remove an OSD\":{}}}", safe),
-        };
-        safe
+        }
+        if pools.iter().any(|pool| pool.margin.is_some_and(|margin| margin < 1)) {
+            safe = false;
+        }
+        QuickReview { safe, pools }
+    }
+
+    // Quick check to see if every PG keeps at least one member over its
+    // pool's `min_size`, or `k` for erasure coded pools. Pending when the
+    // smallest margin of a pool is from PGs that are not active+clean.
+    pub fn quick_diag(self, format: Format) -> Status {
+        let review = self.quick_review();
+        review.print(format);
+        review.status()
     }
 
     // Maps out PGs and their states to each OSD in their `acting` list.
-    // Returns a more general `Status` based on whether there is a removable
-    // OSD or not.
     // `cluster_diag` holds an OSD's removability status. Using a binary heap we
     // can always know which state it has that holds the highest precedent.
-    pub fn exhaustive_diag(self, format: Format) -> Status {
-        let mut pg_diags: Vec<PgDiag> = Vec::new();
-        let mut cluster_diag = ClusterDiag::new();
+    // Every OSD in the OSD map gets a status, the ones no PG maps to have
+    // nothing left to lose and are removable, unless they are down but still
+    // in and the cluster is not clean, as the PGs may be waiting on them to
+    // come back. OSDs are kept by id, so they come out in order.
+    fn cluster_diag(&self) -> ClusterDiag {
+        let mut osd_diags: BTreeMap<i32, OsdDiag> = self
+            .osd_map
+            .osds
+            .iter()
+            .map(|osd| (osd.osd, OsdDiag::new(osd.osd, Some(OsdState::new(osd)))))
+            .collect();
 
-        // Populate PG statuses. For each PG we push it's list of acting OSDs
-        // and the state of the PG
-        for pg_stat in self.pg_map.pg_stats {
-            for acting in pg_stat.acting {
-                pg_diags.push(PgDiag::new(
-                    acting,
-                    PgInfo::new(&pg_stat.state, pg_stat.pgid.clone()),
-                ));
+        // Populate PG statuses. For each PG we push it's acting and up OSDs,
+        // and the OSDs blocking its peering, with the state of the PG. Empty
+        // shard slots have no OSD to push, but a PG that cannot lose another
+        // member is unsafe whatever its state. A PG with no acting OSD at all
+        // could be on any of them.
+        let mut unplaced: Vec<PgInfo> = Vec::new();
+        let mut unclean: Vec<PgInfo> = Vec::new();
+        for pg_stat in &self.pg_map.pg_stats {
+            let mut pg_info = PgInfo::new(&pg_stat.state, pg_stat.pgid.clone());
+            if let Some(pool) = pg_stat.pool_id().and_then(|id| self.osd_map.pool(id)) {
+                pg_info.pool = pool.pool_name.clone();
+            }
+            if self.at_min_members(pg_stat) {
+                pg_info.rm_safety = RmSafety::None;
+                pg_info.rule = SafetyRule::AtMinMembers;
+            }
+            if pg_stat.acting_osds().next().is_none() {
+                unplaced.push(pg_info.clone());
+            }
+            if pg_info.status() != Status::Safe {
+                unclean.push(pg_info.clone());
+            }
+            let mut osds: Vec<i32> = pg_stat
+                .acting_osds()
+                .chain(pg_stat.up_osds())
+                .chain(pg_stat.blocked_by.iter().flatten().cloned())
+                .collect();
+            osds.sort_unstable();
+            osds.dedup();
+            for osd_id in osds {
+                osd_diags
+                    .entry(osd_id)
+                    .or_insert_with(|| OsdDiag::new(osd_id, None))
+                    .push(pg_info.clone());
             }
         }
 
-        // Generate OSD removability.
-        for pg in &pg_diags {
-            if cluster_diag
-                .osd_diags
-                .iter_mut()
-                .find(|ref osd| osd.osd_id == pg.osd_id)
-                .is_none()
-            {
-                cluster_diag.osd_diags.push(OsdDiag::new(pg.osd_id));
-            } else if let Some(osd) = cluster_diag
-                .osd_diags
-                .iter_mut()
-                .find(|ref osd| osd.osd_id == pg.osd_id)
-            {
-                match pg.pg_info.rm_safety {
-                    RmSafety::None => osd.osd_status.push(Status::NonSafe),
-                    RmSafety::Pending => osd.osd_status.push(Status::Unknown),
-                    RmSafety::Total => osd.osd_status.push(Status::Safe),
+        // An OSD no PG maps to is empty unless a PG is not mapped anywhere,
+        // or it is down and in while PGs are not clean
+        for osd in osd_diags.values_mut() {
+            if osd.pgs.is_empty() {
+                osd.osd_status.push(Status::Safe);
+                for pg_info in &unplaced {
+                    osd.push(pg_info.clone());
+                }
+                if osd.state.is_some_and(|state| !state.up && state.osd_in) {
+                    for pg_info in unclean.iter().filter(|pg| !unplaced.contains(pg)) {
+                        osd.push(pg_info.clone());
+                    }
                 }
             }
         }
+        let mut cluster_diag = ClusterDiag::new();
+        cluster_diag.osd_diags = osd_diags.into_values().collect();
+        cluster_diag
+    }
+
+    // Error out with every id in `osd_ids` that is not in the OSD map
+    pub(crate) fn check_osds(&self, osd_ids: &[i32]) -> Result<(), CSDError> {
+        let unknown: Vec<i32> = osd_ids
+            .iter()
+            .filter(|id| self.osd_map.osd(**id).is_none())
+            .cloned()
+            .collect();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(CSDError::UnknownOsd(unknown))
+        }
+    }
+
+    // Returns a more general `Status` based on whether there is a removable
+    // OSD or not.
+    pub fn exhaustive_diag(self, format: Format) -> Status {
+        let mut cluster_diag = self.cluster_diag();
 
         // Print the statuses of OSDs based on `format`
         cluster_diag.print(format);
         cluster_diag.status()
     }
+
+    // Same as `exhaustive_diag` but only for the OSDs in `osd_ids`. The
+    // returned `Status` answers whether all of the given OSDs can be removed.
+    // OSDs that are not in the OSD map are an error rather than being skipped.
+    pub fn osd_diag(self, osd_ids: &[i32], format: Format) -> Result<Status, CSDError> {
+        self.check_osds(osd_ids)?;
+
+        let mut cluster_diag = self.cluster_diag();
+        cluster_diag.retain_osds(osd_ids);
+
+        cluster_diag.print(format);
+        Ok(cluster_diag.status())
+    }
+
+    // The `Status` `exhaustive_diag`, or `osd_diag` if `osd_ids` is given,
+    // would return, without printing anything
+    pub fn removal_status(&self, osd_ids: Option<&[i32]>) -> Result<Status, CSDError> {
+        let mut cluster_diag = self.cluster_diag();
+        if let Some(osd_ids) = osd_ids {
+            self.check_osds(osd_ids)?;
+            cluster_diag.retain_osds(osd_ids);
+        }
+        Ok(cluster_diag.status())
+    }
+
+    // Every OSD `exhaustive_diag` reports on, with its status, by OSD id
+    pub(crate) fn osd_statuses(&self) -> Vec<(i32, Status)> {
+        self.cluster_diag()
+            .osd_diags
+            .iter()
+            .filter_map(|osd| osd.osd_status.peek().map(|status| (osd.osd_id, *status)))
+            .collect()
+    }
+
+    // `exhaustive_diag`, or `osd_diag` if `osd_ids` is given, listing the PGs
+    // behind each OSD's status and the rule that fired for each of them
+    pub fn explain_diag(self, osd_ids: Option<&[i32]>, format: Format) -> Result<Status, CSDError> {
+        let (status, explain) = self.cluster_explain(osd_ids)?;
+        explain.print(format);
+        Ok(status)
+    }
+
+    // What `explain_diag` prints, with the `Status` it returns
+    pub(crate) fn cluster_explain(
+        &self,
+        osd_ids: Option<&[i32]>,
+    ) -> Result<(Status, ClusterExplain), CSDError> {
+        let mut cluster_diag = self.cluster_diag();
+        if let Some(osd_ids) = osd_ids {
+            self.check_osds(osd_ids)?;
+            cluster_diag.retain_osds(osd_ids);
+        }
+        Ok((cluster_diag.status(), cluster_diag.explain()))
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use crate::from::FromFile;
-    use crate::osdmap::OsdMap;
-    use crate::pgmap::PGMap;
 
     #[test]
     fn quick_diag_jewel_safe() {
         let status = DiagMap {
             pg_map: PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap(),
             osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
         }.quick_diag(Format::Pretty);
 
-        assert_eq!(status, true);
+        assert_eq!(status, Status::Safe);
     }
 
     #[test]
@@ -290,6 +794,8 @@ mod tests {
         let status: Status = DiagMap {
             pg_map: PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap(),
             osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
         }.exhaustive_diag(Format::Json);
 
         assert_eq!(status, Status::Safe);
@@ -300,6 +806,8 @@ mod tests {
         let status: Status = DiagMap {
             pg_map: PGMap::from_file("test/jewel/pg_dump_non_safe.json").unwrap(),
             osd_map: OsdMap::from_file("test/jewel/osd_dump_non_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
         }.exhaustive_diag(Format::Pretty);
 
         assert_eq!(status, Status::NonSafe);
@@ -310,6 +818,8 @@ mod tests {
         let status: Status = DiagMap {
             pg_map: PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap(),
             osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
         }.exhaustive_diag(Format::Json);
 
         assert_eq!(status, Status::Safe);
@@ -320,6 +830,8 @@ mod tests {
         let status: Status = DiagMap {
             pg_map: PGMap::from_file("test/luminous/pg_dump_non_safe.json").unwrap(),
             osd_map: OsdMap::from_file("test/luminous/osd_dump_non_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
         }.exhaustive_diag(Format::Pretty);
 
         assert_eq!(status, Status::NonSafe);
@@ -330,6 +842,8 @@ mod tests {
         let status: Status = DiagMap {
             pg_map: PGMap::from_file("test/jewel/pg_dump_pending.json").unwrap(),
             osd_map: OsdMap::from_file("test/jewel/osd_dump_pending.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
         }.exhaustive_diag(Format::Json);
 
         assert_eq!(status, Status::Unknown);
@@ -340,9 +854,11 @@ mod tests {
         let status = DiagMap {
             pg_map: PGMap::from_file("test/firefly/pg_dump_safe.json").unwrap(),
             osd_map: OsdMap::from_file("test/firefly/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
         }.quick_diag(Format::Json);
 
-        assert_eq!(status, true);
+        assert_eq!(status, Status::Safe);
     }
 
     #[test]
@@ -350,9 +866,408 @@ mod tests {
         let status: Status = DiagMap {
             pg_map: PGMap::from_file("test/firefly/pg_dump_safe.json").unwrap(),
             osd_map: OsdMap::from_file("test/firefly/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
         }.exhaustive_diag(Format::Pretty);
 
         assert_eq!(status, Status::Safe);
     }
 
+    #[test]
+    fn osd_diag_jewel_safe() {
+        let status = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }.osd_diag(&[0, 2], Format::Pretty)
+            .unwrap();
+
+        assert_eq!(status, Status::Safe);
+    }
+
+    #[test]
+    fn osd_diag_jewel_non_safe() {
+        let status = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_non_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_non_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }.osd_diag(&[1], Format::Json)
+            .unwrap();
+
+        assert_eq!(status, Status::NonSafe);
+    }
+
+    #[test]
+    fn osd_diag_jewel_no_pgs() {
+        // osd.3 is out and holds no PGs
+        let status = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_pending.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_pending.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }.osd_diag(&[3], Format::Json)
+            .unwrap();
+
+        assert_eq!(status, Status::Safe);
+    }
+
+    #[test]
+    fn exhaustive_diag_jewel_empty_osd() {
+        // osd.3 is down and out, no PG maps to it but it is in the OSD map
+        let cluster_diag = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }.cluster_diag();
+        let review = ClusterReview::from_diag(&cluster_diag);
+        assert_eq!(review.removable, vec![0, 1, 2, 3]);
+        let osd = review.osds.iter().find(|osd| osd.osd_id == 3).unwrap();
+        assert!(osd.empty);
+        assert_eq!(
+            osd.state,
+            Some(OsdState {
+                up: false,
+                osd_in: false,
+                exists: true,
+                destroyed: false,
+            })
+        );
+        let osd = cluster_diag.osd_diags.iter().find(|osd| osd.osd_id == 3).unwrap();
+        assert_eq!(osd.verdict(Status::Safe), "Removable, empty (down, out)");
+        let osd = cluster_diag.osd_diags.iter().find(|osd| osd.osd_id == 0).unwrap();
+        assert_eq!(osd.verdict(Status::Safe), "Removable (up, in)");
+    }
+
+    #[test]
+    fn osd_diag_jewel_down_in_osd() {
+        // osd.2 is down but still in, its PGs are degraded waiting for it and
+        // it is in none of their acting or up sets
+        let diag_map = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_down_in.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_down_in.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        };
+        let cluster_diag = diag_map.cluster_diag();
+        let review = ClusterReview::from_diag(&cluster_diag);
+        let osd = review.osds.iter().find(|osd| osd.osd_id == 2).unwrap();
+        assert!(!osd.empty);
+        assert_eq!(diag_map.removal_status(Some(&[2])).unwrap(), Status::NonSafe);
+        // osd.3 is down and out, nothing waits for it
+        assert_eq!(diag_map.removal_status(Some(&[3])).unwrap(), Status::Safe);
+    }
+
+    #[test]
+    fn osd_diag_jewel_up_and_blocked_by() {
+        let mut pg_map = PGMap::from_file("test/jewel/pg_dump_pending.json").unwrap();
+        let pending = pg_map
+            .pg_stats
+            .iter()
+            .position(|pg_stat| RmSafety::new(&pg_stat.state) != RmSafety::Total)
+            .unwrap();
+        // The PG backfills to osd.3 and its peering waits on osd.2
+        pg_map.pg_stats[pending].up = vec![crate::pgmap::ShardSlot::Osd(3)];
+        pg_map.pg_stats[pending].blocked_by = Some(vec![2]);
+        let diag_map = DiagMap {
+            pg_map,
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_pending.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        };
+        assert_ne!(diag_map.removal_status(Some(&[3])).unwrap(), Status::Safe);
+        let cluster_diag = diag_map.cluster_diag();
+        let osd = cluster_diag.osd_diags.iter().find(|osd| osd.osd_id == 2).unwrap();
+        assert!(osd.pgs.iter().any(|pg| pg.pg_id == diag_map.pg_map.pg_stats[pending].pgid));
+    }
+
+    #[test]
+    fn exhaustive_diag_luminous_unmapped_pgs() {
+        // No PG has an acting OSD, so osd.0 is not known to be empty
+        let cluster_diag = DiagMap {
+            pg_map: PGMap::from_file("test/luminous/pg_dump_non_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/luminous/osd_dump_non_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }.cluster_diag();
+        let review = ClusterReview::from_diag(&cluster_diag);
+        assert_eq!(review.not_removable, vec![0]);
+        assert!(!review.osds[0].empty);
+    }
+
+    fn luminous_first_pg() -> DiagMap {
+        DiagMap {
+            pg_map: PGMap::from_file("test/luminous/pg_dump_first_pg.json").unwrap(),
+            ..luminous_ec_degraded()
+        }
+    }
+
+    #[test]
+    fn exhaustive_diag_luminous_first_pg_unsafe() {
+        // 1.0 is down and the first PG listed for osd.5, osd.0 and osd.2,
+        // every other PG of theirs is active+clean
+        let cluster_diag = luminous_first_pg().cluster_diag();
+        let review = ClusterReview::from_diag(&cluster_diag);
+        assert_eq!(review.not_removable, vec![0, 2, 5]);
+        let explain = cluster_diag.explain();
+        let osd = explain.osds.iter().find(|osd| osd.osd_id == 5).unwrap();
+        assert_eq!(osd.pgs.len(), 1);
+        assert_eq!(osd.pgs[0].pg_id, "1.0");
+        assert_eq!(osd.pgs[0].rule, SafetyRule::UnsafeState);
+        assert_eq!(luminous_first_pg().osd_diag(&[5], Format::Json).unwrap(), Status::NonSafe);
+    }
+
+    #[test]
+    fn exhaustive_diag_luminous_single_pg_osd() {
+        // osd.7 only holds 1.4, which is active+clean
+        let cluster_diag = luminous_first_pg().cluster_diag();
+        let osd = cluster_diag.osd_diags.iter().find(|osd| osd.osd_id == 7).unwrap();
+        assert_eq!(osd.pgs.len(), 1);
+        assert_eq!(osd.osd_status.peek(), Some(&Status::Safe));
+        assert_eq!(osd.verdict(Status::Safe), "Removable (up, in)");
+        // Every acting set member of every PG is counted once, by OSD id
+        let ids: Vec<i32> = cluster_diag.osd_diags.iter().map(|osd| osd.osd_id).collect();
+        assert_eq!(ids, (0..8).collect::<Vec<i32>>());
+        let members: usize = luminous_first_pg()
+            .pg_map
+            .pg_stats
+            .iter()
+            .map(|pg_stat| pg_stat.acting_osds().count())
+            .sum();
+        let counted: usize = cluster_diag.osd_diags.iter().map(|osd| osd.pgs.len()).sum();
+        assert_eq!(counted, members);
+    }
+
+    #[test]
+    fn exhaustive_diag_osd_missing_from_map() {
+        // A PG mapped to an OSD the OSD map does not have still counts,
+        // first PG included
+        let mut diag_map = luminous_first_pg();
+        diag_map.osd_map.osds.retain(|osd| osd.osd != 5);
+        let cluster_diag = diag_map.cluster_diag();
+        let osd = cluster_diag.osd_diags.iter().find(|osd| osd.osd_id == 5).unwrap();
+        assert_eq!(osd.state, None);
+        assert_eq!(osd.osd_status.peek(), Some(&Status::NonSafe));
+        assert_eq!(osd.verdict(Status::NonSafe), "Not removable");
+    }
+
+    #[test]
+    fn osd_diag_jewel_unknown_osd() {
+        let result = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }.osd_diag(&[1, 42], Format::Pretty);
+
+        match result {
+            Err(CSDError::UnknownOsd(ids)) => assert_eq!(ids, vec![42]),
+            _ => panic!("expected an unknown OSD error"),
+        }
+    }
+
+    fn luminous_ec_degraded() -> DiagMap {
+        let mut ec_profiles = BTreeMap::new();
+        ec_profiles.insert(
+            "ec22".to_string(),
+            ErasureCodeProfile::from_file("test/luminous/erasure_code_profile_ec22.json").unwrap(),
+        );
+        DiagMap {
+            pg_map: PGMap::from_file("test/luminous/pg_dump_ec_degraded.json").unwrap(),
+            osd_map: OsdMap::from_file("test/luminous/osd_dump_racks.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles,
+        }
+    }
+
+    #[test]
+    fn quick_diag_jewel_non_safe() {
+        let status = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_non_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_non_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }.quick_diag(Format::Json);
+
+        assert_eq!(status, Status::NonSafe);
+    }
+
+    #[test]
+    fn quick_diag_jewel_pending() {
+        // Every PG still has 3 `up` members over `min_size` 2, but they are
+        // all peering
+        let diag_map = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_pending.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_pending.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        };
+        let review = diag_map.quick_review();
+        assert!(review.safe);
+        assert_eq!(review.pools[0].margin, Some(1));
+        assert!(review.pools[0].pending);
+        assert_eq!(diag_map.quick_diag(Format::Pretty), Status::Unknown);
+    }
+
+    #[test]
+    fn exhaustive_diag_nautilus_to_squid() {
+        for release in &["nautilus", "octopus", "pacific", "quincy", "reef", "squid"] {
+            let diag_map = DiagMap {
+                pg_map: PGMap::from_file(&format!("test/{}/pg_dump_safe.json", release)).unwrap(),
+                osd_map: OsdMap::from_file(&format!("test/{}/osd_dump_safe.json", release)).unwrap(),
+                crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+                ec_profiles: BTreeMap::new(),
+            };
+            assert!(diag_map.quick_review().safe, "{}", release);
+            assert_eq!(diag_map.exhaustive_diag(Format::Json), Status::Safe, "{}", release);
+        }
+    }
+
+    #[test]
+    fn exhaustive_diag_reef_petabyte() {
+        let diag_map = DiagMap {
+            pg_map: PGMap::from_file("test/reef/pg_dump_petabyte.json").unwrap(),
+            osd_map: OsdMap::from_file("test/reef/osd_dump_petabyte.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        };
+        assert!(diag_map.quick_review().safe);
+        assert_eq!(diag_map.exhaustive_diag(Format::Json), Status::Safe);
+    }
+
+    #[test]
+    fn quick_review_luminous_racks() {
+        let review = DiagMap {
+            pg_map: PGMap::from_file("test/luminous/pg_dump_racks.json").unwrap(),
+            ..luminous_ec_degraded()
+        }.quick_review();
+        assert!(review.safe);
+        let margins: Vec<(&str, usize, Option<i32>)> = review
+            .pools
+            .iter()
+            .map(|pool| (pool.pool.as_str(), pool.pgs, pool.margin))
+            .collect();
+        assert_eq!(
+            margins,
+            vec![("rbd", 32, Some(1)), ("backups", 16, Some(1)), ("ecpool", 16, Some(1))]
+        );
+    }
+
+    #[test]
+    fn quick_review_luminous_ec_hole() {
+        // Healthy `rbd` and `backups` PGs do not hide the degraded `ecpool` one
+        let review = luminous_ec_degraded().quick_review();
+        assert!(!review.safe);
+        assert_eq!(review.pools[0].margin, Some(1));
+        assert_eq!(review.pools[2].margin, Some(0));
+    }
+
+    #[test]
+    fn explain_luminous_ec_hole() {
+        let explain = luminous_ec_degraded().cluster_diag().explain();
+        let osd = explain.osds.iter().find(|osd| osd.osd_id == 1).unwrap();
+        assert_eq!(osd.status, Status::NonSafe);
+        assert_eq!(osd.pgs.len(), 1);
+        assert_eq!(osd.pgs[0].pg_id, "3.0");
+        assert_eq!(osd.pgs[0].pool, "ecpool");
+        assert_eq!(osd.pgs[0].pg_state, "active+recovering+degraded");
+        assert_eq!(osd.pgs[0].rule, SafetyRule::AtMinMembers);
+        // Removable OSDs only report how many PGs they hold
+        let osd = explain.osds.iter().find(|osd| osd.osd_id == 2).unwrap();
+        assert_eq!(osd.status, Status::Safe);
+        assert!(osd.pgs.is_empty());
+        assert!(osd.pg_count > 0);
+    }
+
+    #[test]
+    fn explain_diag_jewel_non_safe() {
+        let status = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_non_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_non_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }.explain_diag(Some(&[1]), Format::Pretty)
+            .unwrap();
+
+        assert_eq!(status, Status::NonSafe);
+    }
+
+    #[test]
+    fn exhaustive_diag_luminous_ec_hole() {
+        use crate::mapper::CRUSH_ITEM_NONE;
+        let cluster_diag = luminous_ec_degraded().cluster_diag();
+        assert!(cluster_diag
+            .osd_diags
+            .iter()
+            .all(|osd| osd.osd_id != CRUSH_ITEM_NONE));
+    }
+
+    #[test]
+    fn osd_diag_luminous_ec_hole() {
+        // 3.0 lost its second shard and is down to `min_size` 3, none of the
+        // OSDs holding the other shards can go even though it is recovering
+        let status = luminous_ec_degraded().osd_diag(&[1], Format::Json).unwrap();
+        assert_eq!(status, Status::NonSafe);
+        // osd.2 held the missing shard and only clean PGs otherwise
+        let status = luminous_ec_degraded().osd_diag(&[2], Format::Json).unwrap();
+        assert_eq!(status, Status::Safe);
+    }
+
+    #[test]
+    fn diag_map_from_recorded_backend() {
+        use crate::backend::RecordedBackend;
+        let diag_map = DiagMap::from_backend(&RecordedBackend::new("test/luminous/recorded")).unwrap();
+        assert_eq!(diag_map.osd_map.osds.len(), 3);
+        assert!(diag_map.crush_map.is_ok());
+    }
+
+    #[test]
+    fn diag_map_from_mock_backend() {
+        use crate::backend::MockBackend;
+        let backend = MockBackend::new()
+            .respond_from_file("osd dump", "test/luminous/osd_dump_racks.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/luminous/pg_dump_racks.json"))
+            .and_then(|backend| {
+                backend.respond_from_file("osd crush dump", "test/luminous/osd_crush_dump_racks.json")
+            })
+            .and_then(|backend| {
+                backend.respond_from_file(
+                    "osd erasure-code-profile get ec22",
+                    "test/luminous/erasure_code_profile_ec22.json",
+                )
+            })
+            .unwrap();
+        let diag_map = DiagMap::from_backend(&backend).unwrap();
+        assert_eq!(diag_map.ec_profiles["ec22"].k(), Some(2));
+        assert_eq!(
+            backend.calls(),
+            vec!["osd dump", "osd erasure-code-profile get ec22", "pg dump", "osd crush dump"]
+        );
+    }
+
+    #[test]
+    fn diag_map_from_mock_backend_without_crush() {
+        use crate::backend::MockBackend;
+        let backend = MockBackend::new()
+            .respond_from_file("osd dump", "test/jewel/osd_dump_safe.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_safe.json"))
+            .unwrap()
+            .respond("osd crush dump", "not json");
+        let diag_map = DiagMap::from_backend(&backend).unwrap();
+        assert!(diag_map.crush_map.is_err());
+        assert_eq!(diag_map.removal_status(None).unwrap(), Status::Safe);
+        match diag_map.simulate_placement(&[0]) {
+            Err(CSDError::CrushError(err)) => assert!(err.contains("JSON decoding error")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn diag_map_from_mock_backend_missing() {
+        use crate::backend::MockBackend;
+        let backend = MockBackend::new().respond("osd dump", "{}");
+        assert!(DiagMap::from_backend(&backend).is_err());
+    }
 }
@@ -0,0 +1,317 @@
+use ansi_term::Colour;
+use crate::backend::CephBackend;
+use crate::diag::{DiagMap, Format, Status};
+use crate::error::CSDError;
+use crate::exit::ExitStatus;
+use crate::pgstate::RmSafety;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How the OSDs are emptied
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum DrainMethod {
+    // `ceph osd out`, the OSDs keep their CRUSH weight
+    Out,
+    // `ceph osd crush reweight osd.N 0`, the OSDs' hosts lose the weight too
+    Reweight,
+}
+
+impl DrainMethod {
+    // The mon commands that start draining `osd_ids`
+    pub(crate) fn commands(self, osd_ids: &[i32]) -> Vec<String> {
+        match self {
+            DrainMethod::Out => {
+                let ids: Vec<String> = osd_ids.iter().map(|id| id.to_string()).collect();
+                vec![format!("osd out {}", ids.join(" "))]
+            }
+            DrainMethod::Reweight => osd_ids
+                .iter()
+                .map(|id| format!("osd crush reweight osd.{} 0", id))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DrainOptions {
+    pub method: DrainMethod,
+    // Time between two polls
+    pub interval: Duration,
+    // How long to wait for the backfill before giving up
+    pub timeout: Duration,
+}
+
+// A poll where the number of PGs left on the OSDs changed
+#[derive(Serialize, Debug)]
+struct DrainProgress {
+    elapsed: u64,
+    // PGs with one of the OSDs in their `up` or `acting` set
+    remaining: usize,
+    initial: usize,
+    // PGs in the cluster that are not `active+clean`
+    unclean: usize,
+}
+
+impl DrainProgress {
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => {
+                let moved = self.initial.saturating_sub(self.remaining);
+                let percent = if self.initial == 0 {
+                    100.0
+                } else {
+                    moved as f64 * 100.0 / self.initial as f64
+                };
+                println!(
+                    "{} {}s: {} PGs left, {}/{} moved ({:.0}%), {} PGs not active+clean",
+                    Colour::Yellow.paint("●"),
+                    self.elapsed,
+                    self.remaining,
+                    moved,
+                    self.initial,
+                    percent,
+                    self.unclean
+                );
+            }
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+}
+
+impl DiagMap {
+    // PGs still mapped to one of `osd_ids`, and PGs anywhere that are not
+    // `active+clean`
+    fn drain_counts(&self, osd_ids: &[i32]) -> (usize, usize) {
+        let remaining = self
+            .pg_map
+            .pg_stats
+            .iter()
+            .filter(|pg_stat| {
+                pg_stat
+                    .acting_osds()
+                    .chain(pg_stat.up_osds())
+                    .any(|osd| osd_ids.contains(&osd))
+            })
+            .count();
+        let unclean = self
+            .pg_map
+            .pg_stats
+            .iter()
+            .filter(|pg_stat| RmSafety::new(&pg_stat.state) != RmSafety::Total)
+            .count();
+        (remaining, unclean)
+    }
+}
+
+// Empty the OSDs in `osd_ids` and wait for their PGs to backfill elsewhere.
+// Nothing is issued unless every one of them is removable and no PG would go
+// inactive with all of them gone, see `joint_removal_status`. The
+// drain is done once no PG maps to the OSDs any more and every PG in the
+// cluster is `active+clean` again.
+pub fn drain<B: CephBackend + ?Sized>(
+    backend: &B,
+    osd_ids: &[i32],
+    options: DrainOptions,
+    format: Format,
+) -> Result<ExitStatus, CSDError> {
+    let diag_map = DiagMap::from_backend(backend)?;
+    let status = diag_map.joint_removal_status(osd_ids)?;
+    if status != Status::Safe {
+        println!(
+            "{} Refusing to drain, the OSDs are {}",
+            Colour::Red.paint("●"),
+            status
+        );
+        return Ok(ExitStatus::from(status));
+    }
+    let (initial, _) = diag_map.drain_counts(osd_ids);
+
+    for cmd in options.method.commands(osd_ids) {
+        if let Format::Pretty = format {
+            println!("Running `ceph {}`", cmd);
+        }
+        backend.mon_command(&cmd)?;
+    }
+
+    poll_drained(backend, osd_ids, initial, options, format)
+}
+
+// Wait for `osd_ids` to be drained, as `drain` does, without issuing
+// anything. For OSDs that were already marked out or reweighted.
+pub fn wait_drained<B: CephBackend + ?Sized>(
+    backend: &B,
+    osd_ids: &[i32],
+    options: DrainOptions,
+    format: Format,
+) -> Result<ExitStatus, CSDError> {
+    let diag_map = DiagMap::from_backend(backend)?;
+    diag_map.check_osds(osd_ids)?;
+    let (initial, _) = diag_map.drain_counts(osd_ids);
+    poll_drained(backend, osd_ids, initial, options, format)
+}
+
+// Poll until no PG maps to `osd_ids` and every PG is `active+clean`, or
+// `options.timeout` has passed. `initial` is the number of PGs on the OSDs
+// when the drain started.
+fn poll_drained<B: CephBackend + ?Sized>(
+    backend: &B,
+    osd_ids: &[i32],
+    initial: usize,
+    options: DrainOptions,
+    format: Format,
+) -> Result<ExitStatus, CSDError> {
+    let start = Instant::now();
+    let mut last_remaining = None;
+    loop {
+        match DiagMap::from_backend(backend) {
+            Ok(diag_map) => {
+                let (remaining, unclean) = diag_map.drain_counts(osd_ids);
+                if last_remaining != Some(remaining) {
+                    DrainProgress {
+                        elapsed: start.elapsed().as_secs(),
+                        remaining,
+                        initial,
+                        unclean,
+                    }
+                    .print(format);
+                    last_remaining = Some(remaining);
+                }
+                if remaining == 0 && unclean == 0 {
+                    if let Format::Pretty = format {
+                        println!(
+                            "{} Drained, no PGs map to the OSDs and every PG is active+clean",
+                            Colour::Green.paint("●")
+                        );
+                    }
+                    return Ok(ExitStatus::SafeRm);
+                }
+            }
+            Err(err) => warn!("Polling the cluster failed, {}", err),
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= options.timeout {
+            if let Format::Pretty = format {
+                println!(
+                    "{} Timed out after {}s waiting for the backfill",
+                    Colour::Red.paint("●"),
+                    elapsed.as_secs()
+                );
+            }
+            return Ok(ExitStatus::Timeout);
+        }
+        thread::sleep(options.interval.min(options.timeout - elapsed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+
+    const RACKS: &str = "test/luminous/pg_dump_racks.json";
+    const DRAINED: &str = "test/luminous/pg_dump_racks_drained.json";
+
+    // The luminous racks cluster whose `pg dump` goes through `pg_dumps`
+    fn luminous_racks(pg_dumps: &[&str]) -> MockBackend {
+        let mut backend = MockBackend::new()
+            .respond_from_file("osd dump", "test/luminous/osd_dump_racks.json")
+            .and_then(|backend| {
+                backend.respond_from_file("osd crush dump", "test/luminous/osd_crush_dump_racks.json")
+            })
+            .and_then(|backend| {
+                backend.respond_from_file(
+                    "osd erasure-code-profile get ec22",
+                    "test/luminous/erasure_code_profile_ec22.json",
+                )
+            })
+            .unwrap()
+            .respond("osd out 5", "")
+            .respond("osd crush reweight osd.5 0", "");
+        for pg_dump in pg_dumps {
+            backend = backend.respond_from_file("pg dump", pg_dump).unwrap();
+        }
+        backend
+    }
+
+    fn options(method: DrainMethod, timeout: Duration) -> DrainOptions {
+        DrainOptions {
+            method,
+            interval: Duration::from_millis(0),
+            timeout,
+        }
+    }
+
+    #[test]
+    fn drain_out() {
+        let backend = luminous_racks(&[RACKS, RACKS, DRAINED]);
+        let exit = drain(&backend, &[5], options(DrainMethod::Out, Duration::from_secs(60)), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::SafeRm);
+        let calls = backend.calls();
+        assert_eq!(calls.iter().filter(|cmd| *cmd == "osd out 5").count(), 1);
+        // The pre-check, then one poll with the PGs still there
+        assert_eq!(calls.iter().filter(|cmd| *cmd == "pg dump").count(), 3);
+    }
+
+    #[test]
+    fn drain_reweight() {
+        let backend = luminous_racks(&[RACKS, DRAINED]);
+        let exit = drain(
+            &backend,
+            &[5],
+            options(DrainMethod::Reweight, Duration::from_secs(60)),
+            Format::Json,
+        );
+        assert_eq!(exit.unwrap(), ExitStatus::SafeRm);
+        assert!(backend.calls().contains(&"osd crush reweight osd.5 0".to_string()));
+        assert!(!backend.calls().contains(&"osd out 5".to_string()));
+    }
+
+    #[test]
+    fn drain_timeout() {
+        let backend = luminous_racks(&[RACKS]);
+        let exit = drain(&backend, &[5], options(DrainMethod::Out, Duration::from_millis(20)), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::Timeout);
+    }
+
+    #[test]
+    fn drain_refused() {
+        let backend = luminous_racks(&["test/luminous/pg_dump_ec_degraded.json"]);
+        let exit = drain(&backend, &[1], options(DrainMethod::Out, Duration::from_secs(60)), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::NonSafeRm);
+        assert!(!backend.calls().iter().any(|cmd| cmd.starts_with("osd out")));
+    }
+
+    #[test]
+    fn drain_wait_only() {
+        let backend = luminous_racks(&[RACKS, DRAINED]);
+        let exit = wait_drained(&backend, &[5], options(DrainMethod::Out, Duration::from_secs(60)), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::SafeRm);
+        assert!(!backend.calls().iter().any(|cmd| cmd.starts_with("osd out")));
+        let exit = wait_drained(&backend, &[99], options(DrainMethod::Out, Duration::from_secs(60)), Format::Json);
+        assert!(exit.is_err());
+    }
+
+    #[test]
+    fn drain_refused_together() {
+        // osd.0 and osd.2 are each removable, but PG 1.0 needs one of them
+        let backend = luminous_racks(&[RACKS]);
+        let exit = drain(&backend, &[0, 2], options(DrainMethod::Out, Duration::from_secs(60)), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::NonSafeRm);
+        assert!(!backend.calls().iter().any(|cmd| cmd.starts_with("osd out")));
+        let diag_map = DiagMap::from_backend(&backend).unwrap();
+        assert_eq!(diag_map.removal_status(Some(&[0, 2])).unwrap(), Status::Safe);
+    }
+
+    #[test]
+    fn drain_counts_luminous_racks() {
+        let diag_map = DiagMap::from_backend(&luminous_racks(&[DRAINED])).unwrap();
+        assert_eq!(diag_map.drain_counts(&[5]), (0, 0));
+        assert_eq!(diag_map.drain_counts(&[4]).1, 0);
+        assert!(diag_map.drain_counts(&[4]).0 > 0);
+    }
+}
@@ -12,6 +12,10 @@ pub enum CSDError {
     Utf8Error(string::FromUtf8Error),
     CephExecError(String),
     ExecError,
+    UnknownOsd(Vec<i32>),
+    CrushError(String),
+    UnknownBucket(String),
+    SnapshotVersion(u32),
 }
 
 impl fmt::Display for CSDError {
@@ -22,10 +26,23 @@ impl fmt::Display for CSDError {
             CSDError::Utf8Error(ref err) => write!(f, "UTF-8 conversion error, {}", err),
             CSDError::CephExecError(ref err) => write!(f, "Error executing `ceph`, {}", err),
             CSDError::ExecError => write!(f, "Must be run as root or ceph user"),
+            CSDError::UnknownOsd(ref ids) => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                write!(f, "OSD not found in the OSD map, {}", ids.join(", "))
+            }
+            CSDError::CrushError(ref err) => write!(f, "CRUSH error, {}", err),
+            CSDError::UnknownBucket(ref name) => {
+                write!(f, "CRUSH bucket not found in the CRUSH map, {}", name)
+            }
+            CSDError::SnapshotVersion(version) => {
+                write!(f, "Snapshot version {} is newer than this tool understands", version)
+            }
         }
     }
 }
 
+// Still on the deprecated `description` and `cause` rather than `source`
+#[allow(deprecated, bare_trait_objects)]
 impl StdError for CSDError {
     fn description(&self) -> &str {
         match *self {
@@ -34,6 +51,10 @@ impl StdError for CSDError {
             CSDError::Utf8Error(ref err) => err.description(),
             CSDError::CephExecError(ref err) => err,
             CSDError::ExecError => "Must be run as root or ceph user",
+            CSDError::UnknownOsd(ref _ids) => "OSD not found in the OSD map",
+            CSDError::CrushError(ref err) => err,
+            CSDError::UnknownBucket(ref _name) => "CRUSH bucket not found in the CRUSH map",
+            CSDError::SnapshotVersion(_) => "Snapshot version is newer than this tool understands",
         }
     }
     fn cause(&self) -> Option<&StdError> {
@@ -43,6 +64,10 @@ impl StdError for CSDError {
             CSDError::Utf8Error(ref err) => err.cause(),
             CSDError::CephExecError(ref _err) => None,
             CSDError::ExecError => None,
+            CSDError::UnknownOsd(ref _ids) => None,
+            CSDError::CrushError(ref _err) => None,
+            CSDError::UnknownBucket(ref _name) => None,
+            CSDError::SnapshotVersion(_) => None,
         }
     }
 }
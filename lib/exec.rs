@@ -6,7 +6,7 @@ use std::process::Command;
 pub fn call_ceph(cmd: &str) -> Result<String, CSDError> {
     debug!("calling ceph {} -f json", cmd);
     let ceph = Command::new("/usr/bin/env")
-        .args(&["sh", "-c", &format!("ceph {} -f json", cmd)])
+        .args(["sh", "-c", &format!("ceph {} -f json", cmd)])
         .output()?;
     if ceph.status.success() {
         let stdout = String::from_utf8(ceph.stdout)?;
@@ -1,6 +1,42 @@
-#[derive(Copy, Clone)]
+use crate::diag::Status;
+
+// The exit code contract of `ceph-safe-disk`, shared by every mode:
+//
+// 0: the OSDs checked are safe to remove
+// 1: they are not safe to remove
+// 2: the check could not be run, e.g. bad arguments or ceph is unreachable
+// 3: PGs are still settling, the check should be retried later
+// 4: `--watch` or `--drain` gave up before the OSDs were removable or drained
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ExitStatus {
-    SafeRm,
-    NonSafeRm,
-    Err,
+    SafeRm = 0,
+    NonSafeRm = 1,
+    Err = 2,
+    Pending = 3,
+    Timeout = 4,
+}
+
+impl From<Status> for ExitStatus {
+    fn from(status: Status) -> ExitStatus {
+        match status {
+            Status::Safe => ExitStatus::SafeRm,
+            Status::NonSafe => ExitStatus::NonSafeRm,
+            Status::Unknown => ExitStatus::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExitStatus;
+    use crate::diag::Status;
+
+    #[test]
+    fn exit_status_codes() {
+        assert_eq!(ExitStatus::from(Status::Safe) as i32, 0);
+        assert_eq!(ExitStatus::from(Status::NonSafe) as i32, 1);
+        assert_eq!(ExitStatus::Err as i32, 2);
+        assert_eq!(ExitStatus::from(Status::Unknown) as i32, 3);
+        assert_eq!(ExitStatus::Timeout as i32, 4);
+    }
 }
@@ -1,5 +1,5 @@
+use crate::backend::{CephBackend, CliBackend};
 use crate::error::CSDError;
-use crate::exec::call_ceph;
 
 use std::fmt::Debug;
 use std::fs::File;
@@ -24,13 +24,19 @@ impl<T: DeserializeOwned + Debug> FromFile<T> for T {
 // Generic trait to create structs from ceph JSON output. Since most if not all
 // of of ceph's commands can be formatted to JSON. For example:
 // let pgmap = PGMap::from_ceph("pg dump").unwrap()
+// `from_ceph` runs the `ceph` tool, `from_backend` asks any `CephBackend`.
 pub trait FromCeph<T> {
     fn from_ceph(cmd: &str) -> Result<T, CSDError>;
+    fn from_backend<B: CephBackend + ?Sized>(backend: &B, cmd: &str) -> Result<T, CSDError>;
 }
 
 impl<T: DeserializeOwned + Debug> FromCeph<T> for T {
     fn from_ceph(cmd: &str) -> Result<T, CSDError> {
-        let ceph_output = call_ceph(cmd)?;
+        T::from_backend(&CliBackend, cmd)
+    }
+
+    fn from_backend<B: CephBackend + ?Sized>(backend: &B, cmd: &str) -> Result<T, CSDError> {
+        let ceph_output = backend.mon_command(cmd)?;
         let serde_res: Result<T, serde_json::Error> = serde_json::from_str(&ceph_output);
         trace!("deserialize ceph: {:?}", serde_res);
 
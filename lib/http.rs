@@ -0,0 +1,238 @@
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// How long a client may take to send its request or read the response
+const TIMEOUT: Duration = Duration::from_secs(10);
+// The most read of a request line and its headers, the rest is ignored
+const MAX_REQUEST: u64 = 16 * 1024;
+// The most connections handled at once, more are answered 503 and closed
+const MAX_CONNECTIONS: usize = 16;
+
+// Just enough HTTP/1.0 to answer GET requests from Prometheus or curl. Each
+// connection is handled on its own thread, up to `MAX_CONNECTIONS` of them,
+// and closed after the response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    // The path without its query string
+    pub path: String,
+    pub query: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: String) -> Response {
+        Response {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", format!("{}\n", body))
+    }
+
+    pub fn json(status: u16, body: String) -> Response {
+        Response::new(status, "application/json", body)
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+// Parse the request line and skip the headers
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Ok(None),
+    };
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+    let (path, query) = match target.find('?') {
+        Some(index) => (target[..index].to_string(), Some(target[index + 1..].to_string())),
+        None => (target, None),
+    };
+    Ok(Some(Request {
+        method,
+        path,
+        query,
+    }))
+}
+
+fn write_response<W: Write>(writer: &mut W, response: &Response) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.0 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    writer.flush()
+}
+
+fn handle<F: Fn(&Request) -> Response>(stream: TcpStream, handler: &F) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?).take(MAX_REQUEST);
+    let response = match read_request(&mut reader)? {
+        Some(ref request) if request.method == "GET" => handler(request),
+        Some(_) => Response::text(405, "Only GET is supported"),
+        None => Response::text(400, "Malformed request"),
+    };
+    let mut stream = stream;
+    write_response(&mut stream, &response)
+}
+
+// One of the `MAX_CONNECTIONS`, given back when dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(active: &Arc<AtomicUsize>) -> Option<Slot> {
+        if active.fetch_add(1, Ordering::SeqCst) < MAX_CONNECTIONS {
+            Some(Slot(Arc::clone(active)))
+        } else {
+            active.fetch_sub(1, Ordering::SeqCst);
+            None
+        }
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Answer 503 without waiting for the request, the response fits in the
+// socket's buffer and a client that is not reading does not hold us up
+fn reject(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(true)?;
+    write_response(&mut stream, &Response::text(503, "Too many connections"))
+}
+
+// Answer every connection on `listener` with `handler`, forever
+pub fn serve<F>(listener: TcpListener, handler: F)
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let slot = match Slot::take(&active) {
+                    Some(slot) => slot,
+                    None => {
+                        if let Err(err) = reject(stream) {
+                            warn!("HTTP connection failed, {}", err);
+                        }
+                        continue;
+                    }
+                };
+                let handler = Arc::clone(&handler);
+                thread::spawn(move || {
+                    let _slot = slot;
+                    if let Err(err) = handle(stream, &*handler) {
+                        warn!("HTTP connection failed, {}", err);
+                    }
+                });
+            }
+            Err(err) => warn!("HTTP connection failed, {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn http_read_request() {
+        let mut reader = Cursor::new("GET /metrics?x=1 HTTP/1.1\r\nHost: stor-01\r\n\r\n");
+        let request = read_request(&mut reader).unwrap().unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/metrics");
+        assert_eq!(request.query, Some("x=1".to_string()));
+        assert_eq!(read_request(&mut Cursor::new("\r\n")).unwrap(), None);
+    }
+
+    #[test]
+    fn http_request_capped() {
+        let long = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(64 * 1024));
+        let mut reader = Cursor::new(long).take(MAX_REQUEST);
+        assert_eq!(read_request(&mut reader).unwrap().unwrap().path, "/");
+        assert_eq!(reader.limit(), 0);
+    }
+
+    #[test]
+    fn http_idle_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, |request| Response::text(200, &request.path)));
+        // Connected but never sending anything
+        let _idle = TcpStream::connect(addr).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.ends_with("/metrics\n"));
+    }
+
+    #[test]
+    fn http_too_many_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, |request| Response::text(200, &request.path)));
+        // Every slot taken by a client that never sends anything
+        let _idle: Vec<TcpStream> = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 503 Service Unavailable\r\n"));
+    }
+
+    #[test]
+    fn http_write_response() {
+        let mut buffer = Vec::new();
+        write_response(&mut buffer, &Response::text(404, "Not found")).unwrap();
+        let response = String::from_utf8(buffer).unwrap();
+        assert!(response.starts_with("HTTP/1.0 404 Not Found\r\n"));
+        assert!(response.contains("Content-Length: 10\r\n"));
+        assert!(response.ends_with("\r\n\r\nNot found\n"));
+    }
+}
@@ -3,11 +3,28 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+pub mod backend;
+pub mod cache;
+pub mod capacity;
+mod crush_ln_table;
+pub mod crushmap;
 pub mod diag;
+pub mod drain;
 mod error;
 pub mod exec;
 pub mod exit;
 pub mod from;
+pub mod http;
+pub mod mapper;
+pub mod metrics;
+pub mod movement;
+pub mod nagios;
 pub mod osdmap;
 pub mod pgmap;
+pub mod plan;
 mod pgstate;
+pub mod remove;
+pub mod serve;
+pub mod simulate;
+pub mod snapshot;
+pub mod watch;
@@ -0,0 +1,308 @@
+use crate::backend::CephBackend;
+use crate::cache::{unix_time, CachedDiag, DiagCache};
+use crate::diag::{DiagMap, Status};
+use crate::error::CSDError;
+use crate::exit::ExitStatus;
+use crate::http::{self, Request, Response};
+use crate::pgstate::RmSafety;
+
+use std::fs;
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+const PREFIX: &str = "ceph_safe_disk";
+
+// Statuses are exported as the exit status the same check would end with
+fn status_value(status: Status) -> f64 {
+    f64::from(ExitStatus::from(status) as i32)
+}
+
+// Label values are quoted, with backslashes, quotes and newlines escaped
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Prometheus text exposition format, one metric family at a time
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn gauge(&mut self, name: &str, help: &str, samples: &[(String, f64)]) {
+        self.text
+            .push_str(&format!("# HELP {}_{} {}\n", PREFIX, name, help));
+        self.text.push_str(&format!("# TYPE {}_{} gauge\n", PREFIX, name));
+        for (labels, value) in samples {
+            if labels.is_empty() {
+                self.text.push_str(&format!("{}_{} {}\n", PREFIX, name, value));
+            } else {
+                self.text
+                    .push_str(&format!("{}_{}{{{}}} {}\n", PREFIX, name, labels, value));
+            }
+        }
+    }
+}
+
+impl DiagMap {
+    // Removability of the cluster in the Prometheus text format.
+    // `collected_at` is when the maps were fetched, `up` whether the last
+    // attempt to fetch them worked.
+    pub fn metrics(&self, collected_at: u64, up: bool) -> String {
+        let mut exposition = Exposition { text: String::new() };
+        exposition.gauge(
+            "up",
+            "Whether the last attempt to fetch the maps from ceph worked",
+            &[(String::new(), if up { 1.0 } else { 0.0 })],
+        );
+
+        let osds = self.osd_statuses();
+        let osd_samples: Vec<(String, f64)> = osds
+            .iter()
+            .map(|(osd, status)| (format!("osd=\"{}\"", osd), status_value(*status)))
+            .collect();
+        exposition.gauge(
+            "osd_status",
+            "OSD removability, 0 removable, 1 not removable, 3 pending",
+            &osd_samples,
+        );
+        let removable: Vec<(String, f64)> = osds
+            .iter()
+            .map(|(osd, status)| {
+                let value = if *status == Status::Safe { 1.0 } else { 0.0 };
+                (format!("osd=\"{}\"", osd), value)
+            })
+            .collect();
+        exposition.gauge("osd_removable", "Whether the OSD is removable", &removable);
+        let count = osds
+            .iter()
+            .filter(|(_, status)| *status == Status::Safe)
+            .count();
+        exposition.gauge(
+            "removable_osds",
+            "Number of removable OSDs",
+            &[(String::new(), count as f64)],
+        );
+        let status = self.removal_status(None).unwrap_or(Status::NonSafe);
+        exposition.gauge(
+            "cluster_status",
+            "Status of the exhaustive check, 0 removable, 1 not removable, 3 pending",
+            &[(String::new(), status_value(status))],
+        );
+
+        let margins: Vec<(String, f64)> = self
+            .quick_review()
+            .pools
+            .iter()
+            .filter_map(|pool| {
+                pool.margin.map(|margin| {
+                    (format!("pool=\"{}\"", escape(&pool.pool)), f64::from(margin))
+                })
+            })
+            .collect();
+        exposition.gauge(
+            "pool_margin",
+            "Fewest members any PG of the pool has over what it needs to stay active",
+            &margins,
+        );
+
+        let mut safety = [0; 3];
+        for pg_stat in &self.pg_map.pg_stats {
+            match RmSafety::new(&pg_stat.state) {
+                RmSafety::None => safety[0] += 1,
+                RmSafety::Pending => safety[1] += 1,
+                RmSafety::Total => safety[2] += 1,
+            }
+        }
+        exposition.gauge(
+            "pgs",
+            "PGs by removal safety of their state, none, pending or total",
+            &[
+                ("safety=\"none\"".to_string(), f64::from(safety[0])),
+                ("safety=\"pending\"".to_string(), f64::from(safety[1])),
+                ("safety=\"total\"".to_string(), f64::from(safety[2])),
+            ],
+        );
+
+        exposition.gauge(
+            "osdmap_epoch",
+            "Epoch of the OSD map the metrics are from",
+            &[(String::new(), f64::from(self.osd_map.epoch))],
+        );
+        exposition.gauge(
+            "collected_timestamp_seconds",
+            "When the maps were fetched, in seconds since the Unix epoch",
+            &[(String::new(), collected_at as f64)],
+        );
+        exposition.gauge(
+            "age_seconds",
+            "Seconds since the maps were fetched",
+            &[(String::new(), unix_time().saturating_sub(collected_at) as f64)],
+        );
+        exposition.text
+    }
+}
+
+fn metrics_response(cache: &DiagCache, request: &Request) -> Response {
+    match request.path.as_ref() {
+        "/metrics" => match cache.get() {
+            Some(cached) => {
+                let CachedDiag {
+                    ref diag_map,
+                    collected_at,
+                } = *cached;
+                Response::new(
+                    200,
+                    "text/plain; version=0.0.4",
+                    diag_map.metrics(collected_at, cache.last_error().is_none()),
+                )
+            }
+            None => Response::text(
+                503,
+                &cache
+                    .last_error()
+                    .unwrap_or_else(|| "No maps fetched yet".to_string()),
+            ),
+        },
+        "/" => Response::text(200, "ceph-safe-disk exporter, metrics are at /metrics"),
+        _ => Response::text(404, "Not found"),
+    }
+}
+
+// Serve `/metrics` on `addr`, fetching the maps from `backend` every
+// `interval`. Only returns if `addr` cannot be bound.
+pub fn export<B: CephBackend + ?Sized>(
+    backend: &B,
+    addr: &str,
+    interval: Duration,
+) -> Result<(), CSDError> {
+    let listener = TcpListener::bind(addr)?;
+    let cache = DiagCache::new();
+    let served = cache.clone();
+    thread::spawn(move || http::serve(listener, move |request| metrics_response(&served, request)));
+    cache.refresh_every(backend, interval)
+}
+
+// Write the metrics for node_exporter's textfile collector. The file is
+// written next to `path` and renamed over it so a scrape never reads half of
+// it.
+pub fn write_textfile(diag_map: &DiagMap, collected_at: u64, path: &str) -> Result<(), CSDError> {
+    let partial = format!("{}.{}.tmp", path, std::process::id());
+    fs::write(&partial, diag_map.metrics(collected_at, true))?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::from::FromFile;
+    use crate::osdmap::OsdMap;
+    use crate::pgmap::PGMap;
+    use std::collections::BTreeMap;
+
+    fn jewel(pg_dump: &str) -> DiagMap {
+        DiagMap {
+            pg_map: PGMap::from_file(pg_dump).unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }
+    }
+
+    // The value of the sample `sample`, e.g. `ceph_safe_disk_up`
+    fn sample(metrics: &str, sample: &str) -> Option<f64> {
+        metrics
+            .lines()
+            .find(|line| line.starts_with(sample) && line[sample.len()..].starts_with(' '))
+            .and_then(|line| line[sample.len()..].trim().parse().ok())
+    }
+
+    #[test]
+    fn metrics_jewel_safe() {
+        let metrics = jewel("test/jewel/pg_dump_safe.json").metrics(unix_time(), true);
+        assert_eq!(sample(&metrics, "ceph_safe_disk_up"), Some(1.0));
+        assert_eq!(sample(&metrics, "ceph_safe_disk_cluster_status"), Some(0.0));
+        assert_eq!(sample(&metrics, "ceph_safe_disk_osdmap_epoch"), Some(23.0));
+        assert_eq!(sample(&metrics, "ceph_safe_disk_pgs{safety=\"total\"}"), Some(64.0));
+        assert_eq!(sample(&metrics, "ceph_safe_disk_pgs{safety=\"none\"}"), Some(0.0));
+        assert_eq!(sample(&metrics, "ceph_safe_disk_pool_margin{pool=\"rbd\"}"), Some(1.0));
+        assert!(metrics.contains("# TYPE ceph_safe_disk_osd_status gauge\n"));
+        assert!(sample(&metrics, "ceph_safe_disk_age_seconds").unwrap() < 60.0);
+    }
+
+    #[test]
+    fn metrics_jewel_non_safe() {
+        let metrics = jewel("test/jewel/pg_dump_non_safe.json").metrics(0, false);
+        assert_eq!(sample(&metrics, "ceph_safe_disk_up"), Some(0.0));
+        assert_eq!(sample(&metrics, "ceph_safe_disk_cluster_status"), Some(1.0));
+        assert!(sample(&metrics, "ceph_safe_disk_pgs{safety=\"none\"}").unwrap() > 0.0);
+        assert_eq!(sample(&metrics, "ceph_safe_disk_collected_timestamp_seconds"), Some(0.0));
+    }
+
+    #[test]
+    fn metrics_from_snapshot() {
+        use crate::snapshot::Snapshot;
+        let cache = DiagCache::new();
+        cache
+            .refresh(&Snapshot::open("test/luminous/snapshot_racks.json").unwrap())
+            .unwrap();
+        let response = metrics_response(
+            &cache,
+            &Request {
+                method: "GET".to_string(),
+                path: "/metrics".to_string(),
+                query: None,
+            },
+        );
+        assert_eq!(response.status, 200);
+        // As old as the snapshot, not as the refresh
+        assert_eq!(
+            sample(&response.body, "ceph_safe_disk_collected_timestamp_seconds"),
+            Some(1543859445.0)
+        );
+        let age = sample(&response.body, "ceph_safe_disk_age_seconds").unwrap();
+        assert!((age - (unix_time() - 1543859445) as f64).abs() <= 1.0);
+    }
+
+    #[test]
+    fn metrics_escape_labels() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn metrics_textfile() {
+        let path = std::env::temp_dir().join(format!("csd-metrics-{}.prom", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_textfile(&jewel("test/jewel/pg_dump_safe.json"), unix_time(), path).unwrap();
+        let metrics = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        // osd.3 is out and empty, and removable as well
+        assert_eq!(sample(&metrics, "ceph_safe_disk_removable_osds"), Some(4.0));
+    }
+
+    #[test]
+    fn metrics_http_responses() {
+        let cache = DiagCache::new();
+        let request = |path: &str| Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query: None,
+        };
+        assert_eq!(metrics_response(&cache, &request("/metrics")).status, 503);
+        let backend = MockBackend::new()
+            .respond_from_file("osd dump", "test/jewel/osd_dump_safe.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_safe.json"))
+            .and_then(|backend| backend.respond_from_file("osd crush dump", "test/jewel/osd_crush_dump.json"))
+            .unwrap();
+        cache.refresh(&backend).unwrap();
+        let response = metrics_response(&cache, &request("/metrics"));
+        assert_eq!(response.status, 200);
+        assert!(response.body.contains("ceph_safe_disk_osd_removable{osd=\"0\"} 1\n"));
+        assert_eq!(metrics_response(&cache, &request("/other")).status, 404);
+    }
+}
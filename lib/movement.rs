@@ -0,0 +1,316 @@
+use ansi_term::Colour;
+use crate::diag::{DiagMap, Format, Status};
+use crate::error::CSDError;
+use crate::pgmap::slot_osds;
+use std::collections::BTreeSet;
+
+// A PG that has to backfill once the OSDs are out. `bytes` and `objects`
+// count every copy, or shard, written to `to`, which is empty when there is
+// no CRUSH map to place them.
+#[derive(Serialize, Debug, Clone)]
+pub struct PgMove {
+    pub pg_id: String,
+    pub pool: String,
+    pub bytes: u64,
+    pub objects: u64,
+    pub from: Vec<i32>,
+    pub to: Vec<i32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MovementEstimate {
+    pub osds: Vec<i32>,
+    pub status: Status,
+    pub bytes: u64,
+    pub objects: u64,
+    pub pgs: usize,
+    // PGs CRUSH cannot give `size` OSDs any more
+    pub short: usize,
+    pub sources: Vec<i32>,
+    pub destinations: Vec<i32>,
+    pub moves: Vec<PgMove>,
+    // Without a CRUSH map, only the data on the removed OSDs with no
+    // destinations
+    pub approximate: bool,
+}
+
+// Bytes in the largest unit that keeps at least one whole unit
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+impl MovementEstimate {
+    fn new(osd_ids: &[i32]) -> MovementEstimate {
+        MovementEstimate {
+            osds: osd_ids.to_vec(),
+            status: Status::Safe,
+            bytes: 0,
+            objects: 0,
+            pgs: 0,
+            short: 0,
+            sources: Vec::new(),
+            destinations: Vec::new(),
+            moves: Vec::new(),
+            approximate: false,
+        }
+    }
+
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => self.print_pretty(),
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+
+    fn print_pretty(&self) {
+        let osds: Vec<String> = self.osds.iter().map(|id| id.to_string()).collect();
+        println!("Data movement without OSDs {}:", osds.join(", "));
+        println!("    PGs to backfill: {}", self.pgs);
+        println!(
+            "    Data to copy:    {} ({} bytes)",
+            human_bytes(self.bytes),
+            self.bytes
+        );
+        println!("    Objects to copy: {}", self.objects);
+        println!("    Source OSDs:      {}", self.sources.len());
+        if self.approximate {
+            println!("    Destination OSDs: unknown");
+            println!(
+                "{} Approximate, only the data on the OSDs as there is no CRUSH map to place it",
+                Colour::Yellow.paint("●")
+            );
+            return;
+        }
+        println!("    Destination OSDs: {}", self.destinations.len());
+        let colour = match self.status {
+            Status::Safe => Colour::Green,
+            Status::Unknown => Colour::Yellow,
+            Status::NonSafe => Colour::Red,
+        };
+        println!(
+            "{} {}: {} PGs cannot be given all of their copies",
+            colour.paint("●"),
+            self.status,
+            self.short
+        );
+    }
+}
+
+impl DiagMap {
+    // Estimate the backfill once the OSDs in `osd_ids` are weighted out. Each
+    // OSD CRUSH adds to a PG's `up` set receives a full replica, or one shard
+    // for erasure coded pools, of the PG's `num_bytes` and `num_objects`. The
+    // surviving members of the PG are the sources it is copied from. Without
+    // a CRUSH map this falls back to `estimate_held_movement`.
+    pub fn estimate_movement(&self, osd_ids: &[i32]) -> Result<MovementEstimate, CSDError> {
+        self.check_osds(osd_ids)?;
+        let crush_map = match self.crush_map() {
+            Ok(crush_map) => crush_map,
+            Err(_) => return Ok(self.estimate_held_movement(osd_ids)),
+        };
+
+        let mut estimate = MovementEstimate::new(osd_ids);
+        let mut sources = BTreeSet::new();
+        let mut destinations = BTreeSet::new();
+        for remap in self.remap_pgs(crush_map, osd_ids)? {
+            if (slot_osds(&remap.mapped).count() as i32) < remap.pool.size {
+                estimate.short += 1;
+            }
+            let to = remap.targets();
+            if to.is_empty() {
+                continue;
+            }
+            let stat_sum = &remap.pg_stat.stat_sum;
+            let copies = to.len() as u64;
+            let bytes = self.shard_bytes(remap.pool, stat_sum.num_bytes) * copies;
+            let objects = stat_sum.num_objects * copies;
+            let from: Vec<i32> = remap
+                .pg_stat
+                .acting_osds()
+                .filter(|osd| !osd_ids.contains(osd))
+                .collect();
+
+            estimate.bytes += bytes;
+            estimate.objects += objects;
+            estimate.pgs += 1;
+            sources.extend(from.iter().cloned());
+            destinations.extend(to.iter().cloned());
+            estimate.moves.push(PgMove {
+                pg_id: remap.pg_stat.pgid.clone(),
+                pool: remap.pool.pool_name.clone(),
+                bytes,
+                objects,
+                from,
+                to,
+            });
+        }
+        estimate.sources = sources.into_iter().collect();
+        estimate.destinations = destinations.into_iter().collect();
+        if estimate.short > 0 {
+            estimate.status = Status::NonSafe;
+        }
+        Ok(estimate)
+    }
+
+    // Every replica, or shard, a PG has on the OSDs in `osd_ids` has to be
+    // copied somewhere, but where is up to CRUSH. This leaves out the PGs
+    // CRUSH shuffles between the remaining OSDs, so it is only approximate
+    // and its status unknown.
+    fn estimate_held_movement(&self, osd_ids: &[i32]) -> MovementEstimate {
+        let mut estimate = MovementEstimate::new(osd_ids);
+        estimate.approximate = true;
+        estimate.status = Status::Unknown;
+        let mut sources = BTreeSet::new();
+        for pg_stat in &self.pg_map.pg_stats {
+            let copies = pg_stat.acting_osds().filter(|osd| osd_ids.contains(osd)).count() as u64;
+            if copies == 0 {
+                continue;
+            }
+            let pool = match pg_stat.pool_id().and_then(|id| self.osd_map.pool(id)) {
+                Some(pool) => pool,
+                None => continue,
+            };
+            let bytes = self.shard_bytes(pool, pg_stat.stat_sum.num_bytes) * copies;
+            let objects = pg_stat.stat_sum.num_objects * copies;
+            let from: Vec<i32> = pg_stat
+                .acting_osds()
+                .filter(|osd| !osd_ids.contains(osd))
+                .collect();
+
+            estimate.bytes += bytes;
+            estimate.objects += objects;
+            estimate.pgs += 1;
+            sources.extend(from.iter().cloned());
+            estimate.moves.push(PgMove {
+                pg_id: pg_stat.pgid.clone(),
+                pool: pool.pool_name.clone(),
+                bytes,
+                objects,
+                from,
+                to: Vec::new(),
+            });
+        }
+        estimate.sources = sources.into_iter().collect();
+        estimate
+    }
+
+    // Print the result of `estimate_movement` based on `format`
+    pub fn movement_diag(self, osd_ids: &[i32], format: Format) -> Result<Status, CSDError> {
+        let estimate = self.estimate_movement(osd_ids)?;
+        estimate.print(format);
+        Ok(estimate.status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crushmap::CrushMap;
+    use crate::from::FromFile;
+    use crate::osdmap::{ErasureCodeProfile, OsdMap};
+    use crate::pgmap::PGMap;
+    use std::collections::BTreeMap;
+
+    fn luminous_racks() -> DiagMap {
+        let mut ec_profiles = BTreeMap::new();
+        ec_profiles.insert(
+            "ec22".to_string(),
+            ErasureCodeProfile::from_file("test/luminous/erasure_code_profile_ec22.json").unwrap(),
+        );
+        DiagMap {
+            pg_map: PGMap::from_file("test/luminous/pg_dump_racks.json").unwrap(),
+            osd_map: OsdMap::from_file("test/luminous/osd_dump_racks.json").unwrap(),
+            crush_map: Ok(CrushMap::from_file("test/luminous/osd_crush_dump_racks.json").unwrap()),
+            ec_profiles,
+        }
+    }
+
+    #[test]
+    fn movement_single_osd() {
+        let diag_map = luminous_racks();
+        let estimate = diag_map.estimate_movement(&[5]).unwrap();
+        assert_eq!(estimate.status, Status::Safe);
+        // Every PG on osd.5 gets a new copy somewhere else
+        let on_osd = diag_map
+            .pg_map
+            .pg_stats
+            .iter()
+            .filter(|pg_stat| pg_stat.acting_osds().any(|osd| osd == 5))
+            .count();
+        assert_eq!(estimate.pgs, on_osd);
+        assert!(!estimate.sources.contains(&5));
+        assert!(!estimate.destinations.contains(&5));
+        assert_eq!(estimate.bytes, estimate.moves.iter().map(|pg| pg.bytes).sum::<u64>());
+    }
+
+    #[test]
+    fn movement_erasure_shard_bytes() {
+        let diag_map = luminous_racks();
+        let estimate = diag_map.estimate_movement(&[5]).unwrap();
+        let ec_move = estimate.moves.iter().find(|pg| pg.pool == "ecpool").unwrap();
+        let pg_stat = diag_map
+            .pg_map
+            .pg_stats
+            .iter()
+            .find(|pg_stat| pg_stat.pgid == ec_move.pg_id)
+            .unwrap();
+        // A 2+2 shard is half of the PG
+        assert_eq!(ec_move.to.len(), 1);
+        assert_eq!(ec_move.bytes, pg_stat.stat_sum.num_bytes / 2);
+        assert_eq!(ec_move.objects, pg_stat.stat_sum.num_objects);
+        assert_eq!(ec_move.from.len(), 3);
+    }
+
+    #[test]
+    fn movement_without_crush_map() {
+        let diag_map = DiagMap {
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ..luminous_racks()
+        };
+        let estimate = diag_map.estimate_movement(&[5]).unwrap();
+        assert!(estimate.approximate);
+        assert_eq!(estimate.status, Status::Unknown);
+        assert!(estimate.destinations.is_empty());
+        let on_osd: Vec<_> = diag_map
+            .pg_map
+            .pg_stats
+            .iter()
+            .filter(|pg_stat| pg_stat.acting_osds().any(|osd| osd == 5))
+            .collect();
+        assert_eq!(estimate.pgs, on_osd.len());
+        // One replica or shard per PG on osd.5
+        let ec_move = estimate.moves.iter().find(|pg| pg.pool == "ecpool").unwrap();
+        let pg_stat = on_osd.iter().find(|pg_stat| pg_stat.pgid == ec_move.pg_id).unwrap();
+        assert_eq!(ec_move.bytes, pg_stat.stat_sum.num_bytes / 2);
+        assert_eq!(ec_move.from.len(), 3);
+        let rbd_move = estimate.moves.iter().find(|pg| pg.pool == "rbd").unwrap();
+        let pg_stat = on_osd.iter().find(|pg_stat| pg_stat.pgid == rbd_move.pg_id).unwrap();
+        assert_eq!(rbd_move.bytes, pg_stat.stat_sum.num_bytes);
+        assert_eq!(estimate.bytes, estimate.moves.iter().map(|pg| pg.bytes).sum::<u64>());
+    }
+
+    #[test]
+    fn movement_nothing_removed() {
+        let estimate = luminous_racks().estimate_movement(&[]).unwrap();
+        assert_eq!(estimate.pgs, 0);
+        assert_eq!(estimate.bytes, 0);
+        assert!(estimate.sources.is_empty());
+    }
+
+    #[test]
+    fn movement_human_bytes() {
+        assert_eq!(human_bytes(512), "512.0 B");
+        assert_eq!(human_bytes(3 << 29), "1.5 GiB");
+    }
+}
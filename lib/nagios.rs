@@ -0,0 +1,230 @@
+use crate::backend::CephBackend;
+use crate::diag::{DiagMap, Status};
+
+use std::fmt;
+
+// The states and exit codes of the Nagios plugin API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NagiosState {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl fmt::Display for NagiosState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match *self {
+            NagiosState::Ok => "OK",
+            NagiosState::Warning => "WARNING",
+            NagiosState::Critical => "CRITICAL",
+            NagiosState::Unknown => "UNKNOWN",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NagiosThresholds {
+    // Warn when fewer than this many OSDs are removable
+    pub removable: usize,
+    // Critical when a pool's margin is this or less
+    pub margin: i32,
+}
+
+// The single line a Nagios plugin prints, `STATE - message | perfdata`
+#[derive(Debug, Clone)]
+pub struct NagiosCheck {
+    pub state: NagiosState,
+    pub message: String,
+    pub perfdata: Vec<String>,
+}
+
+impl NagiosCheck {
+    pub fn unknown(message: String) -> NagiosCheck {
+        NagiosCheck {
+            state: NagiosState::Unknown,
+            message,
+            perfdata: Vec::new(),
+        }
+    }
+
+    pub fn line(&self) -> String {
+        if self.perfdata.is_empty() {
+            format!("{} - {}", self.state, self.message)
+        } else {
+            format!("{} - {} | {}", self.state, self.message, self.perfdata.join(" "))
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.state as i32
+    }
+}
+
+// A perfdata label, quoted as soon as it has more than letters, digits and
+// underscores. Quotes inside are doubled.
+fn label(name: &str) -> String {
+    if name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\'', "''"))
+    }
+}
+
+impl DiagMap {
+    // Grade the exhaustive diagnosis and the pool margins of `quick_review`
+    // against `thresholds`. Perfdata has the OSD counts by status and the
+    // margin of every pool with PGs.
+    pub fn nagios_check(&self, thresholds: NagiosThresholds) -> NagiosCheck {
+        let osds = self.osd_statuses();
+        let count = |wanted: Status| osds.iter().filter(|(_, status)| *status == wanted).count();
+        let (removable, pending, not_removable) =
+            (count(Status::Safe), count(Status::Unknown), count(Status::NonSafe));
+
+        let review = self.quick_review();
+        let margins: Vec<(&str, i32)> = review
+            .pools
+            .iter()
+            .filter_map(|pool| pool.margin.map(|margin| (pool.pool.as_str(), margin)))
+            .collect();
+        let critical: Vec<String> = margins
+            .iter()
+            .filter(|(_, margin)| *margin <= thresholds.margin)
+            .map(|(pool, margin)| format!("{} ({})", pool, margin))
+            .collect();
+
+        let mut message = format!("{} of {} OSDs removable", removable, osds.len());
+        if pending > 0 {
+            message.push_str(&format!(", {} pending", pending));
+        }
+        let state = if !critical.is_empty() {
+            message = format!(
+                "Pool margin at or under {}: {}, {}",
+                thresholds.margin,
+                critical.join(", "),
+                message
+            );
+            NagiosState::Critical
+        } else if removable < thresholds.removable {
+            NagiosState::Warning
+        } else {
+            NagiosState::Ok
+        };
+
+        // Nagios ranges alert outside of `N:`, i.e. below N
+        let total = osds.len();
+        let mut perfdata = vec![
+            format!("removable={};{}:;;0;{}", removable, thresholds.removable, total),
+            format!("pending={};;;0;{}", pending, total),
+            format!("not_removable={};;;0;{}", not_removable, total),
+        ];
+        for (pool, margin) in &margins {
+            perfdata.push(format!(
+                "{}={};;{}:",
+                label(&format!("margin_{}", pool)),
+                margin,
+                thresholds.margin + 1
+            ));
+        }
+
+        NagiosCheck {
+            state,
+            message,
+            perfdata,
+        }
+    }
+}
+
+// `nagios_check` on the maps from `backend`, UNKNOWN if they cannot be
+// fetched
+pub fn nagios<B: CephBackend + ?Sized>(backend: &B, thresholds: NagiosThresholds) -> NagiosCheck {
+    match DiagMap::from_backend(backend) {
+        Ok(diag_map) => diag_map.nagios_check(thresholds),
+        Err(err) => NagiosCheck::unknown(format!("Could not fetch the maps, {}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::error::CSDError;
+    use crate::from::FromFile;
+    use crate::osdmap::{ErasureCodeProfile, OsdMap};
+    use crate::pgmap::PGMap;
+    use std::collections::BTreeMap;
+
+    const THRESHOLDS: NagiosThresholds = NagiosThresholds {
+        removable: 1,
+        margin: 0,
+    };
+
+    fn jewel(pg_dump: &str) -> DiagMap {
+        DiagMap {
+            pg_map: PGMap::from_file(pg_dump).unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn nagios_jewel_ok() {
+        let check = jewel("test/jewel/pg_dump_safe.json").nagios_check(THRESHOLDS);
+        assert_eq!(check.state, NagiosState::Ok);
+        assert_eq!(check.exit_code(), 0);
+        assert_eq!(
+            check.line(),
+            "OK - 4 of 4 OSDs removable | removable=4;1:;;0;4 pending=0;;;0;4 \
+             not_removable=0;;;0;4 margin_rbd=1;;1:"
+        );
+    }
+
+    #[test]
+    fn nagios_jewel_warning() {
+        let thresholds = NagiosThresholds {
+            removable: 5,
+            ..THRESHOLDS
+        };
+        let check = jewel("test/jewel/pg_dump_safe.json").nagios_check(thresholds);
+        assert_eq!(check.state, NagiosState::Warning);
+        assert!(check.line().starts_with("WARNING - 4 of 4 OSDs removable | removable=4;5:;"));
+    }
+
+    #[test]
+    fn nagios_luminous_ec_hole_critical() {
+        let mut ec_profiles = BTreeMap::new();
+        ec_profiles.insert(
+            "ec22".to_string(),
+            ErasureCodeProfile::from_file("test/luminous/erasure_code_profile_ec22.json").unwrap(),
+        );
+        let check = DiagMap {
+            pg_map: PGMap::from_file("test/luminous/pg_dump_ec_degraded.json").unwrap(),
+            osd_map: OsdMap::from_file("test/luminous/osd_dump_racks.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles,
+        }
+        .nagios_check(THRESHOLDS);
+        assert_eq!(check.state, NagiosState::Critical);
+        assert_eq!(check.exit_code(), 2);
+        assert!(check.message.starts_with("Pool margin at or under 0: ecpool (0), "));
+        assert!(check.perfdata.contains(&"margin_ecpool=0;;1:".to_string()));
+    }
+
+    #[test]
+    fn nagios_unknown_without_maps() {
+        let check = nagios(&MockBackend::new(), THRESHOLDS);
+        assert_eq!(check.state, NagiosState::Unknown);
+        assert_eq!(check.exit_code(), 3);
+        assert!(check.line().starts_with("UNKNOWN - Could not fetch the maps, "));
+        assert!(!check.line().contains('|'));
+    }
+
+    #[test]
+    fn nagios_perfdata_labels() {
+        assert_eq!(label("margin_rbd"), "margin_rbd");
+        assert_eq!(label("margin_.rgw.root"), "'margin_.rgw.root'");
+        assert_eq!(label("margin_it's"), "'margin_it''s'");
+    }
+}
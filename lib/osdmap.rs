@@ -1,109 +1,250 @@
+// See `OSDMap::dump` in `src/osd/OSDMap.cc` in ceph's source. Only the fields
+// the checks use are required, the rest come and go between releases.
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct OsdMap {
-    pub pool_max: i32,
+    pub pool_max: Option<i32>,
     pub max_osd: i32,
-    pub created: String,
-    pub modified: String,
-    pub osd_xinfo: Vec<OsdXinfo>,
+    pub created: Option<String>,
+    pub modified: Option<String>,
+    pub osd_xinfo: Option<Vec<OsdXinfo>>,
     pub osds: Vec<Osds>,
     pub epoch: i32,
-    pub flags: String,
-    pub cluster_snapshot: String,
+    pub flags: Option<String>,
+    pub cluster_snapshot: Option<String>,
     pub pools: Vec<Pools>,
     pub fsid: String,
+    // Moved here from the PG map in Luminous
+    pub full_ratio: Option<f64>,
+    pub backfillfull_ratio: Option<f64>,
+    pub nearfull_ratio: Option<f64>,
 }
 
+impl OsdMap {
+    pub fn osd(&self, osd_id: i32) -> Option<&Osds> {
+        self.osds.iter().find(|osd| osd.osd == osd_id)
+    }
+
+    pub fn pool(&self, pool_id: i32) -> Option<&Pools> {
+        self.pools.iter().find(|pool| pool.pool == pool_id)
+    }
+
+    // OSD reweights as CRUSH sees them: 16.16 fixed point indexed by OSD id,
+    // where `0x10000` is fully in and 0 is out
+    pub fn crush_weights(&self) -> Vec<u32> {
+        let max = self.osds.iter().map(|osd| osd.osd + 1).max().unwrap_or(0);
+        let mut weights = vec![0; max.max(self.max_osd).max(0) as usize];
+        for osd in &self.osds {
+            if let Some(weight) = osd.weight.as_f64() {
+                weights[osd.osd as usize] = (weight * f64::from(0x10000)) as u32;
+            }
+        }
+        weights
+    }
+}
+
+impl Pools {
+    pub fn is_erasure(&self) -> bool {
+        self.pool_type == POOL_TYPE_ERASURE
+    }
+}
+
+impl Osds {
+    pub fn exists(&self) -> bool {
+        self.state.iter().any(|state| state == "exists")
+    }
+
+    // Destroyed OSDs keep their id for a replacement but have no data
+    pub fn destroyed(&self) -> bool {
+        self.state.iter().any(|state| state == "destroyed")
+    }
+}
+
+// Pool `type` for erasure coded pools, replicated pools are 1
+pub const POOL_TYPE_ERASURE: i32 = 3;
+
+// `osd erasure-code-profile get <name>`. Ceph reports every value as a string
 #[derive(Deserialize, Debug, PartialEq, Clone)]
-pub struct Default {
+pub struct ErasureCodeProfile {
     pub k: String,
-    pub technique: String,
+    pub technique: Option<String>,
     pub m: String,
     pub plugin: String,
+    #[serde(rename = "crush-failure-domain")]
+    pub crush_failure_domain: Option<String>,
+}
+
+impl ErasureCodeProfile {
+    // Number of data chunks, the shards needed to rebuild an object
+    pub fn k(&self) -> Option<i32> {
+        self.k.parse().ok()
+    }
+
+    // Number of coding chunks, the shards that can be lost
+    pub fn m(&self) -> Option<i32> {
+        self.m.parse().ok()
+    }
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct Osds {
-    pub heartbeat_back_addr: String,
-    pub uuid: String,
+    // Octopus added `*_addrs` vectors next to these
+    pub heartbeat_back_addr: Option<String>,
+    pub uuid: Option<String>,
     pub weight: serde_json::Value,
-    pub up_from: i32,
-    pub heartbeat_front_addr: String,
-    pub down_at: i32,
+    pub up_from: Option<i32>,
+    pub heartbeat_front_addr: Option<String>,
+    pub down_at: Option<i32>,
     pub up: i32,
-    pub lost_at: i32,
-    pub primary_affinity: serde_json::Value,
+    // 1 while the OSD is in, 0 once it is marked out
+    #[serde(rename = "in")]
+    pub osd_in: i32,
+    pub lost_at: Option<i32>,
+    pub primary_affinity: Option<serde_json::Value>,
     pub state: Vec<String>,
-    pub last_clean_begin: i32,
-    pub last_clean_end: i32,
-    pub public_addr: String,
-    pub up_thru: i32,
-    pub cluster_addr: String,
+    pub last_clean_begin: Option<i32>,
+    pub last_clean_end: Option<i32>,
+    pub public_addr: Option<String>,
+    pub up_thru: Option<i32>,
+    pub cluster_addr: Option<String>,
     pub osd: i32,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct OsdXinfo {
-    pub laggy_probability: serde_json::Value,
-    pub laggy_interval: i32,
-    pub features: u64,
+    pub laggy_probability: Option<serde_json::Value>,
+    pub laggy_interval: Option<i32>,
+    pub features: Option<u64>,
     pub old_weight: Option<i32>,
-    pub down_stamp: String,
+    pub down_stamp: Option<String>,
     pub osd: i32,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct Pools {
-    pub cache_target_full_ratio_micro: i32,
+    pub cache_target_full_ratio_micro: Option<i32>,
     pub fast_read: Option<bool>,
-    pub stripe_width: i32,
-    pub flags_names: String,
-    pub tier_of: i32,
+    pub stripe_width: Option<i32>,
+    pub flags_names: Option<String>,
+    pub tier_of: Option<i32>,
     pub hit_set_grade_decay_rate: Option<i32>,
     pub pg_placement_num: i32,
     pub use_gmt_hitset: Option<bool>,
-    pub quota_max_bytes: i32,
+    pub quota_max_bytes: Option<u64>,
+    // Empty for replicated pools
     pub erasure_code_profile: String,
-    pub expected_num_objects: Option<i32>,
+    pub expected_num_objects: Option<u64>,
     // "replicated size"
     pub size: i32,
-    pub snap_seq: i32,
-    pub auid: i32,
-    pub cache_min_flush_age: i32,
-    pub hit_set_period: i32,
-    pub min_read_recency_for_promote: i32,
-    pub target_max_objects: i32,
+    pub snap_seq: Option<i32>,
+    // Dropped in Nautilus
+    pub auid: Option<i32>,
+    pub cache_min_flush_age: Option<i32>,
+    pub hit_set_period: Option<i32>,
+    pub min_read_recency_for_promote: Option<i32>,
+    pub target_max_objects: Option<u64>,
     pub pg_num: i32,
     pub crush_ruleset: Option<i32>,
     pub crush_rule: Option<i32>,
     pub pool_name: String,
-    pub cache_min_evict_age: i32,
-    pub snap_mode: String,
-    pub cache_mode: String,
+    pub cache_min_evict_age: Option<i32>,
+    pub snap_mode: Option<String>,
+    pub cache_mode: Option<String>,
     pub min_size: i32,
+    // 1 for replicated and 3 for erasure coded pools
+    #[serde(rename = "type")]
+    pub pool_type: i32,
     pub cache_target_dirty_high_ratio_micro: Option<i32>,
     pub crash_replay_interval: Option<i32>,
-    pub object_hash: i32,
-    pub write_tier: i32,
-    pub cache_target_dirty_ratio_micro: i32,
+    pub object_hash: Option<i32>,
+    pub write_tier: Option<i32>,
+    pub cache_target_dirty_ratio_micro: Option<i32>,
     pub pool: i32,
-    pub removed_snaps: String,
-    pub last_force_op_resend: String,
-    pub quota_max_objects: i32,
-    pub hit_set_count: i32,
+    // Dropped in Octopus
+    pub removed_snaps: Option<String>,
+    pub last_force_op_resend: Option<String>,
+    pub quota_max_objects: Option<u64>,
+    pub hit_set_count: Option<i32>,
     pub flags: i32,
-    pub target_max_bytes: i32,
-    pub snap_epoch: i32,
+    pub target_max_bytes: Option<u64>,
+    pub snap_epoch: Option<i32>,
     pub hit_set_search_last_n: Option<i32>,
-    pub last_change: String,
+    pub last_change: Option<String>,
     pub min_write_recency_for_promote: Option<i32>,
-    pub read_tier: i32,
+    pub read_tier: Option<i32>,
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::OsdMap;
     use crate::from::FromFile;
 
+    // Nautilus tests
+    #[test]
+    fn osdmap_from_nautilus_file() {
+        let osdmap = OsdMap::from_file("test/nautilus/osd_dump_safe.json").unwrap();
+        assert_eq!(osdmap.pools.len(), 1);
+        assert_eq!(osdmap.pools.last().unwrap().pool_name, "rbd");
+        assert_eq!(osdmap.osds.len(), 3);
+        // `auid` was dropped from pools
+        assert_eq!(osdmap.pools[0].auid, None);
+    }
+
+    // Octopus tests
+    #[test]
+    fn osdmap_from_octopus_file() {
+        let osdmap = OsdMap::from_file("test/octopus/osd_dump_safe.json").unwrap();
+        assert_eq!(osdmap.pools.len(), 2);
+        assert_eq!(osdmap.pools.last().unwrap().pool_name, "rbd");
+        assert_eq!(osdmap.osds.len(), 3);
+        // `removed_snaps` was dropped from pools
+        assert_eq!(osdmap.pools[0].removed_snaps, None);
+    }
+
+    // Pacific tests
+    #[test]
+    fn osdmap_from_pacific_file() {
+        let osdmap = OsdMap::from_file("test/pacific/osd_dump_safe.json").unwrap();
+        assert_eq!(osdmap.pools.len(), 2);
+        assert_eq!(osdmap.pools.last().unwrap().pool_name, "rbd");
+        assert_eq!(osdmap.osds.len(), 3);
+    }
+
+    // Quincy tests
+    #[test]
+    fn osdmap_from_quincy_file() {
+        let osdmap = OsdMap::from_file("test/quincy/osd_dump_safe.json").unwrap();
+        assert_eq!(osdmap.pools.len(), 2);
+        assert_eq!(osdmap.pools.last().unwrap().pool_name, "rbd");
+        assert_eq!(osdmap.osds.len(), 3);
+    }
+
+    // Reef tests
+    #[test]
+    fn osdmap_from_reef_file() {
+        let osdmap = OsdMap::from_file("test/reef/osd_dump_safe.json").unwrap();
+        assert_eq!(osdmap.pools.len(), 2);
+        assert_eq!(osdmap.pools.last().unwrap().pool_name, "rbd");
+        assert_eq!(osdmap.osds.len(), 3);
+    }
+
+    #[test]
+    fn osdmap_from_reef_petabyte_file() {
+        let osdmap = OsdMap::from_file("test/reef/osd_dump_petabyte.json").unwrap();
+        assert_eq!(osdmap.osds.len(), 60);
+        let rbd = osdmap.pools.last().unwrap();
+        assert_eq!(rbd.quota_max_bytes, Some(4 << 50));
+    }
+
+    // Squid tests
+    #[test]
+    fn osdmap_from_squid_file() {
+        let osdmap = OsdMap::from_file("test/squid/osd_dump_safe.json").unwrap();
+        assert_eq!(osdmap.pools.len(), 2);
+        assert_eq!(osdmap.pools.last().unwrap().pool_name, "rbd");
+        assert_eq!(osdmap.osds.len(), 3);
+    }
+
     // Luminous tests
     #[test]
     #[should_panic]
@@ -163,6 +304,24 @@ mod tests {
         assert_eq!(osds_up.len() as i32, osdmap.pools.first().unwrap().min_size);
     }
 
+    #[test]
+    fn erasure_code_profile_from_luminous_file() {
+        use super::ErasureCodeProfile;
+        let profile =
+            ErasureCodeProfile::from_file("test/luminous/erasure_code_profile_ec22.json").unwrap();
+        assert_eq!(profile.k(), Some(2));
+        assert_eq!(profile.m(), Some(2));
+        assert_eq!(profile.crush_failure_domain, Some("host".to_string()));
+    }
+
+    #[test]
+    fn osdmap_luminous_erasure_pool() {
+        let osdmap = OsdMap::from_file("test/luminous/osd_dump_racks.json").unwrap();
+        assert!(!osdmap.pool(1).unwrap().is_erasure());
+        assert!(osdmap.pool(3).unwrap().is_erasure());
+        assert_eq!(osdmap.pool(3).unwrap().erasure_code_profile, "ec22");
+    }
+
     // Firefly tests
     #[test]
     fn osdmap_from_firefly_file() {
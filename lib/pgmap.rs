@@ -1,104 +1,128 @@
+use crate::mapper::CRUSH_ITEM_NONE;
+use serde::de::{Deserialize, Deserializer, Error};
 use serde_json::Value;
+use std::collections::BTreeMap;
 
-// See `src/mon/PGMap.h` in ceph's source
+// See `src/mon/PGMap.h` in ceph's source. Only the fields the checks use are
+// required, the rest come and go between releases. Since Nautilus `pg dump`
+// wraps the map in `{"pg_ready": true, "pg_map": {...}}`, see the
+// `Deserialize` impl below.
 #[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(remote = "Self")]
 pub struct PGMap {
-    pub osd_stats_sum: OsdStatsSum,
-    pub pg_stats_delta: PgStatsDelta,
+    pub osd_stats_sum: Option<OsdStatsSum>,
+    pub pg_stats_delta: Option<PgStatsDelta>,
     pub min_last_epoch_clean: Option<i32>,
-    pub stamp: String,
-    pub pg_stats_sum: PgStatsSum,
-    pub last_pg_scan: i32,
+    pub stamp: Option<String>,
+    pub pg_stats_sum: Option<PgStatsSum>,
+    pub last_pg_scan: Option<i32>,
+    // Moved to the OSD map in Luminous, which still reports them here as 0
     pub full_ratio: Option<Value>,
-    pub pool_stats: Vec<PoolStats>,
-    pub version: i32,
-    pub last_osdmap_epoch: i32,
+    pub pool_stats: Option<Vec<PoolStats>>,
+    pub version: Option<i32>,
+    pub last_osdmap_epoch: Option<i32>,
     pub near_full_ratio: Option<Value>,
     pub osd_stats: Vec<OsdStats>,
     pub pg_stats: Vec<PgStats>,
 }
 
+impl<'de> Deserialize<'de> for PGMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PGMap, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        if let Some(pg_map) = value.get_mut("pg_map") {
+            value = pg_map.take();
+        }
+        PGMap::deserialize(value).map_err(D::Error::custom)
+    }
+}
+
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct OsdStats {
-    pub snap_trim_queue_len: i32,
-    pub kb: i32,
+    pub snap_trim_queue_len: Option<i32>,
+    pub kb: u64,
+    // `perf_stat` since Nautilus
     pub fs_perf_stat: Option<FsPerfStat>,
+    pub perf_stat: Option<FsPerfStat>,
     pub hb_in: Option<Vec<i32>>,
-    pub num_snap_trimming: i32,
+    pub num_snap_trimming: Option<i32>,
     pub hb_out: Option<Vec<i32>>,
-    pub kb_avail: i32,
-    pub kb_used: i32,
-    pub op_queue_age_hist: OpQueueAgeHist,
+    pub hb_peers: Option<Vec<i32>>,
+    pub num_pgs: Option<i32>,
+    pub kb_avail: u64,
+    pub kb_used: u64,
+    pub op_queue_age_hist: Option<OpQueueAgeHist>,
     pub osd: i32,
 }
 
+// Changes since the last PG map, so counters may be negative
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct PgStatsDelta {
     pub acting: Option<i32>,
-    pub log_size: i32,
-    pub ondisk_log_size: i32,
-    pub stat_sum: StatSum,
+    pub log_size: Option<i32>,
+    pub ondisk_log_size: Option<i32>,
+    pub stat_sum: BTreeMap<String, i64>,
     pub up: Option<i32>,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct StatSum {
-    pub num_evict: Option<i32>,
-    pub num_evict_kb: Option<i32>,
-    pub num_bytes_hit_set_archive: Option<i32>,
-    pub num_whiteouts: i32,
-    pub num_objects_pinned: Option<i32>,
-    pub num_scrub_errors: i32,
-    pub num_evict_mode_full: Option<i32>,
-    pub num_read: i32,
-    pub num_objects_recovered: i32,
-    pub num_objects_omap: i32,
-    pub num_objects_missing_on_primary: i32,
-    pub num_write: i32,
-    pub num_object_clones: i32,
-    pub num_objects: i32,
-    pub num_deep_scrub_errors: i32,
-    pub num_shallow_scrub_errors: i32,
-    pub num_read_kb: i32,
-    pub num_objects_missing: Option<i32>,
-    pub num_flush_kb: Option<i32>,
-    pub num_flush_mode_high: Option<i32>,
-    pub num_write_kb: i32,
-    pub num_evict_mode_some: Option<i32>,
-    pub num_objects_degraded: i32,
-    pub num_flush: Option<i32>,
-    pub num_objects_misplaced: Option<i32>,
-    pub num_bytes_recovered: i32,
-    pub num_objects_hit_set_archive: i32,
-    pub num_keys_recovered: i32,
-    pub num_flush_mode_low: Option<i32>,
-    pub num_objects_unfound: i32,
-    pub num_promote: Option<i32>,
-    pub num_object_copies: i32,
-    pub num_bytes: i32,
-    pub num_objects_dirty: i32,
+    pub num_evict: Option<u64>,
+    pub num_evict_kb: Option<u64>,
+    pub num_bytes_hit_set_archive: Option<u64>,
+    pub num_whiteouts: Option<u64>,
+    pub num_objects_pinned: Option<u64>,
+    pub num_scrub_errors: Option<u64>,
+    pub num_evict_mode_full: Option<u64>,
+    pub num_read: Option<u64>,
+    pub num_objects_recovered: Option<u64>,
+    pub num_objects_omap: Option<u64>,
+    pub num_objects_missing_on_primary: Option<u64>,
+    pub num_write: Option<u64>,
+    pub num_object_clones: Option<u64>,
+    pub num_objects: u64,
+    pub num_deep_scrub_errors: Option<u64>,
+    pub num_shallow_scrub_errors: Option<u64>,
+    pub num_read_kb: Option<u64>,
+    pub num_objects_missing: Option<u64>,
+    pub num_flush_kb: Option<u64>,
+    pub num_flush_mode_high: Option<u64>,
+    pub num_write_kb: Option<u64>,
+    pub num_evict_mode_some: Option<u64>,
+    pub num_objects_degraded: Option<u64>,
+    pub num_flush: Option<u64>,
+    pub num_objects_misplaced: Option<u64>,
+    pub num_bytes_recovered: Option<u64>,
+    pub num_objects_hit_set_archive: Option<u64>,
+    pub num_keys_recovered: Option<u64>,
+    pub num_flush_mode_low: Option<u64>,
+    pub num_objects_unfound: Option<u64>,
+    pub num_promote: Option<u64>,
+    pub num_object_copies: Option<u64>,
+    pub num_bytes: u64,
+    pub num_objects_dirty: Option<u64>,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct PgStatsSum {
     pub acting: Option<i32>,
-    pub log_size: i32,
-    pub ondisk_log_size: i32,
+    pub log_size: Option<i32>,
+    pub ondisk_log_size: Option<i32>,
     pub stat_sum: StatSum,
     pub up: Option<i32>,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct OsdStatsSum {
-    pub snap_trim_queue_len: i32,
-    pub kb: i32,
+    pub snap_trim_queue_len: Option<i32>,
+    pub kb: u64,
     pub fs_perf_stat: Option<FsPerfStat>,
+    pub perf_stat: Option<FsPerfStat>,
     pub hb_in: Option<Vec<i32>>,
-    pub num_snap_trimming: i32,
+    pub num_snap_trimming: Option<i32>,
     pub hb_out: Option<Vec<i32>>,
-    pub kb_avail: i32,
-    pub kb_used: i32,
-    pub op_queue_age_hist: OpQueueAgeHist,
+    pub kb_avail: u64,
+    pub kb_used: u64,
+    pub op_queue_age_hist: Option<OpQueueAgeHist>,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
@@ -115,64 +139,213 @@ pub struct FsPerfStat {
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct PoolStats {
-    pub log_size: i32,
-    pub ondisk_log_size: i32,
+    pub log_size: Option<i32>,
+    pub ondisk_log_size: Option<i32>,
     pub up: Option<i32>,
     pub acting: Option<i32>,
     pub poolid: i32,
     pub stat_sum: StatSum,
 }
 
+// `reported_seq` and `reported_epoch` went from strings to numbers in
+// Pacific, fields that changed type are kept as a `Value`
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct PgStats {
-    pub last_scrub: String,
-    pub last_clean_scrub_stamp: String,
-    pub parent_split_bits: i32,
-    pub last_active: String,
+    pub last_scrub: Option<String>,
+    pub last_clean_scrub_stamp: Option<String>,
+    pub parent_split_bits: Option<i32>,
+    pub last_active: Option<String>,
     pub pin_stats_invalid: Option<bool>,
-    pub reported_epoch: String,
-    pub log_start: String,
-    pub log_size: i32,
+    pub reported_epoch: Option<Value>,
+    pub log_start: Option<String>,
+    pub log_size: Option<i32>,
     pub hitset_stats_invalid: Option<bool>,
-    pub stats_invalid: Value,
-    pub acting_primary: i32,
-    pub reported_seq: String,
-    pub ondisk_log_size: i32,
-    pub mapping_epoch: i32,
+    pub stats_invalid: Option<Value>,
+    pub acting_primary: Option<i32>,
+    pub reported_seq: Option<Value>,
+    pub ondisk_log_size: Option<i32>,
+    pub mapping_epoch: Option<i32>,
     pub dirty_stats_invalid: Option<bool>,
     pub state: String,
-    pub version: String,
+    pub version: Option<String>,
     pub last_became_peered: Option<String>,
     pub last_undegraded: Option<String>,
     pub pgid: String,
-    pub parent: String,
-    pub acting: Vec<i32>,
-    pub up_primary: i32,
+    pub parent: Option<String>,
+    pub acting: Vec<ShardSlot>,
+    pub up_primary: Option<i32>,
     pub last_fullsized: Option<String>,
-    pub last_epoch_clean: i32,
-    pub last_deep_scrub_stamp: String,
+    pub last_epoch_clean: Option<i32>,
+    pub last_deep_scrub_stamp: Option<String>,
     pub stat_sum: StatSum,
-    pub last_deep_scrub: String,
-    pub last_fresh: String,
-    pub last_scrub_stamp: String,
-    pub created: i32,
-    pub up: Vec<i32>,
+    pub last_deep_scrub: Option<String>,
+    pub last_fresh: Option<String>,
+    pub last_scrub_stamp: Option<String>,
+    pub created: Option<i32>,
+    pub up: Vec<ShardSlot>,
     pub hitset_bytes_stats_invalid: Option<bool>,
     pub last_peered: Option<String>,
-    pub last_became_active: String,
+    pub last_became_active: Option<String>,
     pub omap_stats_invalid: Option<bool>,
-    pub last_clean: String,
-    pub last_unstale: String,
-    pub last_change: String,
+    pub last_clean: Option<String>,
+    pub last_unstale: Option<String>,
+    pub last_change: Option<String>,
     pub blocked_by: Option<Vec<i32>>,
-    pub ondisk_log_start: String,
+    pub ondisk_log_start: Option<String>,
+}
+
+// A position in a PG's `acting` or `up` set. Erasure coded pools keep a slot
+// per shard and report a shard without an OSD as `CRUSH_ITEM_NONE`, which is
+// read as `ShardSlot::None` and written back out as `null`.
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(from = "i32", into = "Option<i32>")]
+pub enum ShardSlot {
+    Osd(i32),
+    None,
+}
+
+impl ShardSlot {
+    pub fn osd(self) -> Option<i32> {
+        match self {
+            ShardSlot::Osd(osd_id) => Some(osd_id),
+            ShardSlot::None => None,
+        }
+    }
+
+    pub fn is_none(self) -> bool {
+        self == ShardSlot::None
+    }
+}
+
+impl From<i32> for ShardSlot {
+    fn from(osd_id: i32) -> ShardSlot {
+        if osd_id == CRUSH_ITEM_NONE {
+            ShardSlot::None
+        } else {
+            ShardSlot::Osd(osd_id)
+        }
+    }
+}
+
+impl From<ShardSlot> for Option<i32> {
+    fn from(slot: ShardSlot) -> Option<i32> {
+        slot.osd()
+    }
+}
+
+// The OSDs in a set of slots, skipping the empty ones
+pub fn slot_osds(slots: &[ShardSlot]) -> impl Iterator<Item = i32> + '_ {
+    slots.iter().filter_map(|slot| slot.osd())
+}
+
+impl PgStats {
+    // OSDs currently holding a shard or replica of the PG
+    pub fn acting_osds(&self) -> impl Iterator<Item = i32> + '_ {
+        slot_osds(&self.acting)
+    }
+
+    pub fn up_osds(&self) -> impl Iterator<Item = i32> + '_ {
+        slot_osds(&self.up)
+    }
+
+    // Shards with no OSD in the acting set, redundancy the PG has already lost
+    pub fn missing_shards(&self) -> usize {
+        self.acting.iter().filter(|slot| slot.is_none()).count()
+    }
+
+    // A pgid is `<pool id>.<placement seed in hex>`, e.g. `1.2f`
+    pub fn pool_id(&self) -> Option<i32> {
+        self.pgid.split('.').next().and_then(|pool| pool.parse().ok())
+    }
+
+    // The placement seed of the PG within its pool
+    pub fn ps(&self) -> Option<u32> {
+        self.pgid
+            .split('.')
+            .nth(1)
+            .and_then(|seed| u32::from_str_radix(seed, 16).ok())
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::PGMap;
     use crate::from::FromFile;
 
+    // Nautilus tests
+    #[test]
+    fn pgmap_from_nautilus_file() {
+        let pgmap = PGMap::from_file("test/nautilus/pg_dump_safe.json").unwrap();
+        assert_eq!(pgmap.pg_stats.len(), 16);
+        assert_eq!(pgmap.osd_stats.len(), 3);
+        assert_eq!(pgmap.pg_stats.first().unwrap().up.len(), 3);
+        // Wrapped in `pg_map` and without the full ratios
+        assert!(pgmap.full_ratio.is_none());
+        assert!(pgmap.osd_stats[0].perf_stat.is_some());
+    }
+
+    // Octopus tests
+    #[test]
+    fn pgmap_from_octopus_file() {
+        let pgmap = PGMap::from_file("test/octopus/pg_dump_safe.json").unwrap();
+        assert_eq!(pgmap.pg_stats.len(), 17);
+        assert_eq!(pgmap.osd_stats.len(), 3);
+        assert_eq!(pgmap.pg_stats.first().unwrap().up.len(), 3);
+    }
+
+    // Pacific tests
+    #[test]
+    fn pgmap_from_pacific_file() {
+        let pgmap = PGMap::from_file("test/pacific/pg_dump_safe.json").unwrap();
+        assert_eq!(pgmap.pg_stats.len(), 17);
+        assert_eq!(pgmap.osd_stats.len(), 3);
+        assert_eq!(pgmap.pg_stats.first().unwrap().up.len(), 3);
+        assert!(pgmap.pg_stats[0].reported_seq.as_ref().unwrap().is_u64());
+    }
+
+    // Quincy tests
+    #[test]
+    fn pgmap_from_quincy_file() {
+        let pgmap = PGMap::from_file("test/quincy/pg_dump_safe.json").unwrap();
+        assert_eq!(pgmap.pg_stats.len(), 17);
+        assert_eq!(pgmap.osd_stats.len(), 3);
+        assert_eq!(pgmap.pg_stats.first().unwrap().up.len(), 3);
+    }
+
+    // Reef tests
+    #[test]
+    fn pgmap_from_reef_file() {
+        let pgmap = PGMap::from_file("test/reef/pg_dump_safe.json").unwrap();
+        assert_eq!(pgmap.pg_stats.len(), 17);
+        assert_eq!(pgmap.osd_stats.len(), 3);
+        assert_eq!(pgmap.pg_stats.first().unwrap().up.len(), 3);
+    }
+
+    #[test]
+    fn pgmap_from_reef_petabyte_file() {
+        let pgmap = PGMap::from_file("test/reef/pg_dump_petabyte.json").unwrap();
+        assert_eq!(pgmap.osd_stats.len(), 60);
+        // 16 TiB OSDs overflow 32 bit KiB counters once summed
+        assert!(pgmap.osd_stats_sum.unwrap().kb > u64::from(u32::MAX));
+        // And so does a single one
+        assert_eq!(pgmap.osd_stats[0].kb, 17_578_125_000);
+        assert!(pgmap.osd_stats[0].kb > u64::from(u32::MAX));
+        let stat_sum = pgmap.pg_stats_sum.unwrap().stat_sum;
+        assert!(stat_sum.num_bytes > 1 << 46);
+        let pg_stat = pgmap.pg_stats.iter().find(|pg_stat| pg_stat.pgid == "2.0").unwrap();
+        assert_eq!(pg_stat.stat_sum.num_bytes, 717_727_692_460);
+    }
+
+    // Squid tests
+    #[test]
+    fn pgmap_from_squid_file() {
+        let pgmap = PGMap::from_file("test/squid/pg_dump_safe.json").unwrap();
+        assert_eq!(pgmap.pg_stats.len(), 17);
+        assert_eq!(pgmap.osd_stats.len(), 3);
+        assert_eq!(pgmap.pg_stats.first().unwrap().up.len(), 3);
+    }
+
     // Jewel tests
     #[test]
     #[should_panic]
@@ -215,6 +388,25 @@ mod tests {
         assert_eq!(pgmap.pg_stats.first().unwrap().acting.len() as i32, 2);
     }
 
+    #[test]
+    fn pgmap_pool_id() {
+        let pgmap = PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap();
+        assert_eq!(pgmap.pg_stats.first().unwrap().pool_id(), Some(0));
+        assert_eq!(pgmap.pg_stats.first().unwrap().ps(), Some(0x3f));
+    }
+
+    #[test]
+    fn pgmap_luminous_shard_holes() {
+        use super::ShardSlot;
+        let pgmap = PGMap::from_file("test/luminous/pg_dump_ec_degraded.json").unwrap();
+        let pg_stat = pgmap.pg_stats.iter().find(|pg| pg.pgid == "3.0").unwrap();
+        assert_eq!(pg_stat.acting.len(), 4);
+        assert_eq!(pg_stat.acting[1], ShardSlot::None);
+        assert_eq!(pg_stat.missing_shards(), 1);
+        assert_eq!(pg_stat.acting_osds().count(), 3);
+        assert!(!pg_stat.acting_osds().any(|osd| osd == 2147483647));
+    }
+
     #[test]
     #[should_panic]
     fn pgmap_from_jewel_file_non_safe_panic() {
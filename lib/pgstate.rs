@@ -105,6 +105,7 @@ impl PgState {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::unnecessary_owned_empty_strings)]
 mod tests {
     use super::*;
 
@@ -0,0 +1,350 @@
+use crate::diag::{DiagMap, Format, Status};
+use crate::drain::DrainMethod;
+use crate::error::CSDError;
+use crate::remove::RemoveMethod;
+
+// Cluster flags set while the OSDs drain, so scrubbing does not compete with
+// the backfill
+const DRAIN_FLAGS: [&str; 2] = ["noscrub", "nodeep-scrub"];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum StepKind {
+    // A `ceph-safe-disk` check that has to pass before going on
+    Check,
+    // A command that changes the cluster
+    Command,
+    // A command that blocks until the cluster has caught up
+    Wait,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PlanStep {
+    pub kind: StepKind,
+    pub description: String,
+    pub command: String,
+}
+
+// The result of a check when the plan was made
+#[derive(Serialize, Debug, Clone)]
+pub struct PlanCheck {
+    pub check: String,
+    pub status: Status,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RemovalPlan {
+    pub fsid: String,
+    pub epoch: i32,
+    pub osds: Vec<i32>,
+    pub drain: DrainMethod,
+    pub method: RemoveMethod,
+    // The worst of `checks`
+    pub status: Status,
+    pub checks: Vec<PlanCheck>,
+    pub steps: Vec<PlanStep>,
+}
+
+impl RemovalPlan {
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => print!("{}", self.script()),
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+
+    // The plan as a shell script that stops at the first failing step
+    pub fn script(&self) -> String {
+        let osds: Vec<String> = self.osds.iter().map(|id| id.to_string()).collect();
+        let mut script = String::from("#!/bin/sh\n");
+        script.push_str(&format!(
+            "# Removal plan for OSDs {} of cluster {}, OSD map epoch {}\n",
+            osds.join(", "),
+            self.fsid,
+            self.epoch
+        ));
+        for check in &self.checks {
+            script.push_str(&format!("# {}: {}\n", check.check, check.status));
+        }
+        if self.status != Status::Safe {
+            script.push_str("# The checks do not pass yet, one of the check steps will stop the script\n");
+        }
+        script.push_str("set -e\n");
+        for (number, step) in self.steps.iter().enumerate() {
+            script.push_str(&format!(
+                "\n# {}. {}\n{}\n",
+                number + 1,
+                step.description,
+                step.command
+            ));
+        }
+        script
+    }
+}
+
+fn step(kind: StepKind, description: &str, command: String) -> PlanStep {
+    PlanStep {
+        kind,
+        description: description.to_string(),
+        command,
+    }
+}
+
+impl DiagMap {
+    // The steps to take `osd_ids` out of the cluster with `drain` and then
+    // destroy or purge them, with the checks that have to pass in between.
+    // The current result of each check is part of the plan.
+    pub fn removal_plan(
+        &self,
+        osd_ids: &[i32],
+        drain: DrainMethod,
+        method: RemoveMethod,
+    ) -> Result<RemovalPlan, CSDError> {
+        self.check_osds(osd_ids)?;
+        let ids: Vec<String> = osd_ids.iter().map(|id| id.to_string()).collect();
+        let ids = ids.join(",");
+
+        let mut checks = vec![
+            PlanCheck {
+                check: "Removable".to_string(),
+                status: self.removal_status(Some(osd_ids))?,
+            },
+            PlanCheck {
+                check: "Simulated removal".to_string(),
+                status: self.simulate_removal(osd_ids)?.status,
+            },
+        ];
+        if self.crush_map.is_ok() {
+            checks.push(PlanCheck {
+                check: "Capacity after backfill".to_string(),
+                status: self.project_capacity(osd_ids)?.status,
+            });
+        }
+        let status = checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(Status::Safe);
+
+        let mut steps = vec![
+            step(
+                StepKind::Check,
+                "Check the OSDs are removable",
+                format!("ceph-safe-disk -o {}", ids),
+            ),
+            step(
+                StepKind::Check,
+                "Check no PG loses too many members once they are gone",
+                format!("ceph-safe-disk -s {}", ids),
+            ),
+        ];
+        if self.crush_map.is_ok() {
+            steps.push(step(
+                StepKind::Check,
+                "Check no OSD ends up nearfull after the backfill",
+                format!("ceph-safe-disk -c {}", ids),
+            ));
+        }
+        let unset: Vec<String> = DRAIN_FLAGS
+            .iter()
+            .map(|flag| format!("ceph osd unset {}", flag))
+            .collect();
+        steps.push(step(
+            StepKind::Command,
+            "Let scrubbing resume however the script exits",
+            format!("trap '{}' EXIT", unset.join("; ")),
+        ));
+        for flag in &DRAIN_FLAGS {
+            steps.push(step(
+                StepKind::Command,
+                "Hold off scrubbing during the backfill",
+                format!("ceph osd set {}", flag),
+            ));
+        }
+        for cmd in drain.commands(osd_ids) {
+            steps.push(step(StepKind::Command, "Move the data off the OSDs", format!("ceph {}", cmd)));
+        }
+        steps.push(step(
+            StepKind::Wait,
+            "Wait for the backfill, until no PG maps to the OSDs and every PG is active+clean",
+            format!("ceph-safe-disk -w -D {} --timeout 86400", ids),
+        ));
+        for osd_id in osd_ids {
+            let command = match self.crush_map.as_ref().ok().and_then(|crush| crush.host(*osd_id)) {
+                Some(host) => format!("ssh {} systemctl stop ceph-osd@{}", host.name, osd_id),
+                None => format!("systemctl stop ceph-osd@{}", osd_id),
+            };
+            steps.push(step(StepKind::Command, "Stop the OSD daemon on its host", command));
+        }
+        steps.push(step(
+            StepKind::Check,
+            "Check the OSDs are still removable once stopped",
+            format!("ceph-safe-disk -o {}", ids),
+        ));
+        for osd_id in osd_ids {
+            let description = match method {
+                RemoveMethod::Destroy => "Destroy the OSD, keeping its id for the replacement",
+                RemoveMethod::Purge => "Purge the OSD from the cluster",
+            };
+            steps.push(step(
+                StepKind::Command,
+                description,
+                format!("ceph {}", method.command(*osd_id)),
+            ));
+        }
+        for flag in &DRAIN_FLAGS {
+            steps.push(step(
+                StepKind::Command,
+                "Let scrubbing resume",
+                format!("ceph osd unset {}", flag),
+            ));
+        }
+
+        Ok(RemovalPlan {
+            fsid: self.osd_map.fsid.clone(),
+            epoch: self.osd_map.epoch,
+            osds: osd_ids.to_vec(),
+            drain,
+            method,
+            status,
+            checks,
+            steps,
+        })
+    }
+
+    // `removal_plan` for every OSD under the CRUSH bucket `name`, checking
+    // the pools still find enough failure domains without it as well
+    pub fn bucket_plan(
+        &self,
+        name: &str,
+        drain: DrainMethod,
+        method: RemoveMethod,
+    ) -> Result<RemovalPlan, CSDError> {
+        let mut plan = self.removal_plan(&self.bucket_osds(name)?, drain, method)?;
+        let status = self.simulate_bucket_removal(name)?.status;
+        plan.checks.push(PlanCheck {
+            check: format!("Removal of {}", name),
+            status,
+        });
+        plan.status = plan.status.max(status);
+        plan.steps.insert(
+            0,
+            step(
+                StepKind::Check,
+                "Check the pools keep enough failure domains without the bucket",
+                format!("ceph-safe-disk -H {}", name),
+            ),
+        );
+        Ok(plan)
+    }
+
+    // Print the plan for `osd_ids`, or for the CRUSH bucket `bucket` if it is
+    // given, based on `format`, as a shell script for `Format::Pretty`
+    pub fn plan_diag(
+        self,
+        osd_ids: &[i32],
+        bucket: Option<&str>,
+        drain: DrainMethod,
+        method: RemoveMethod,
+        format: Format,
+    ) -> Result<Status, CSDError> {
+        let plan = match bucket {
+            Some(name) => self.bucket_plan(name, drain, method)?,
+            None => self.removal_plan(osd_ids, drain, method)?,
+        };
+        plan.print(format);
+        Ok(plan.status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crushmap::CrushMap;
+    use crate::from::FromFile;
+    use crate::osdmap::{ErasureCodeProfile, OsdMap};
+    use crate::pgmap::PGMap;
+    use std::collections::BTreeMap;
+
+    fn luminous_racks() -> DiagMap {
+        let mut ec_profiles = BTreeMap::new();
+        ec_profiles.insert(
+            "ec22".to_string(),
+            ErasureCodeProfile::from_file("test/luminous/erasure_code_profile_ec22.json").unwrap(),
+        );
+        DiagMap {
+            pg_map: PGMap::from_file("test/luminous/pg_dump_racks.json").unwrap(),
+            osd_map: OsdMap::from_file("test/luminous/osd_dump_racks.json").unwrap(),
+            crush_map: Ok(CrushMap::from_file("test/luminous/osd_crush_dump_racks.json").unwrap()),
+            ec_profiles,
+        }
+    }
+
+    fn commands(plan: &RemovalPlan) -> Vec<&str> {
+        plan.steps.iter().map(|step| step.command.as_str()).collect()
+    }
+
+    #[test]
+    fn plan_luminous_purge() {
+        let plan = luminous_racks()
+            .removal_plan(&[5], DrainMethod::Out, RemoveMethod::Purge)
+            .unwrap();
+        assert_eq!(plan.status, Status::Safe);
+        assert_eq!(plan.epoch, 311);
+        assert_eq!(plan.checks.len(), 3);
+        assert_eq!(
+            commands(&plan),
+            vec![
+                "ceph-safe-disk -o 5",
+                "ceph-safe-disk -s 5",
+                "ceph-safe-disk -c 5",
+                "trap 'ceph osd unset noscrub; ceph osd unset nodeep-scrub' EXIT",
+                "ceph osd set noscrub",
+                "ceph osd set nodeep-scrub",
+                "ceph osd out 5",
+                "ceph-safe-disk -w -D 5 --timeout 86400",
+                "ssh stor-03 systemctl stop ceph-osd@5",
+                "ceph-safe-disk -o 5",
+                "ceph osd purge osd.5 --yes-i-really-mean-it",
+                "ceph osd unset noscrub",
+                "ceph osd unset nodeep-scrub",
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_luminous_host_destroy() {
+        let plan = luminous_racks()
+            .bucket_plan("stor-04", DrainMethod::Reweight, RemoveMethod::Destroy)
+            .unwrap();
+        // The erasure coded pool needs all four hosts
+        assert_eq!(plan.status, Status::NonSafe);
+        assert_eq!(plan.checks.last().unwrap().status, Status::NonSafe);
+        let commands = commands(&plan);
+        assert_eq!(commands[0], "ceph-safe-disk -H stor-04");
+        assert!(commands.contains(&"ceph osd crush reweight osd.6 0"));
+        assert!(commands.contains(&"ceph osd destroy osd.7 --yes-i-really-mean-it"));
+        assert!(plan.script().contains("The checks do not pass yet"));
+    }
+
+    #[test]
+    fn plan_jewel_without_crush_map() {
+        let diag_map = DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        };
+        let plan = diag_map
+            .removal_plan(&[3], DrainMethod::Out, RemoveMethod::Purge)
+            .unwrap();
+        assert_eq!(plan.checks.len(), 2);
+        assert!(commands(&plan).contains(&"systemctl stop ceph-osd@3"));
+        let script = plan.script();
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("\n# 1. Check the OSDs are removable\nceph-safe-disk -o 3\n"));
+    }
+}
@@ -0,0 +1,349 @@
+use ansi_term::Colour;
+use crate::backend::CephBackend;
+use crate::diag::{DiagMap, Format, Status};
+use crate::error::CSDError;
+use crate::exit::ExitStatus;
+
+// What happens to the OSDs once they are removable
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum RemoveMethod {
+    // `ceph osd destroy`, the id and CRUSH position are kept for a replacement
+    Destroy,
+    // `ceph osd purge`, the OSD is gone from the CRUSH, OSD and auth maps
+    Purge,
+}
+
+impl RemoveMethod {
+    pub(crate) fn command(self, osd_id: i32) -> String {
+        match self {
+            RemoveMethod::Destroy => format!("osd destroy osd.{} --yes-i-really-mean-it", osd_id),
+            RemoveMethod::Purge => format!("osd purge osd.{} --yes-i-really-mean-it", osd_id),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum RemoveOutcome {
+    // Every command went through
+    Removed,
+    // The OSDs are not removable, nothing was issued
+    Refused,
+    // The confirmation prompt was answered no, nothing was issued
+    Declined,
+    // A command failed, the ones before it went through
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IssuedCommand {
+    pub command: String,
+    pub error: Option<String>,
+}
+
+// What `remove_osds` checked and did
+#[derive(Serialize, Debug)]
+pub struct RemovalRecord {
+    pub osds: Vec<i32>,
+    pub method: RemoveMethod,
+    pub outcome: RemoveOutcome,
+    pub status: Status,
+    pub reason: Option<String>,
+    // OSD map epoch the verdict was first reached at
+    pub checked_epoch: i32,
+    // OSD map epoch right before the commands were issued
+    pub epoch: Option<i32>,
+    pub issued: Vec<IssuedCommand>,
+}
+
+impl RemovalRecord {
+    pub fn exit_status(&self) -> ExitStatus {
+        match self.outcome {
+            RemoveOutcome::Removed => ExitStatus::SafeRm,
+            RemoveOutcome::Refused if self.status == Status::Safe => ExitStatus::NonSafeRm,
+            RemoveOutcome::Refused => ExitStatus::from(self.status),
+            RemoveOutcome::Declined | RemoveOutcome::Failed => ExitStatus::Err,
+        }
+    }
+
+    pub fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => self.print_pretty(),
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+
+    fn print_pretty(&self) {
+        for issued in &self.issued {
+            match issued.error {
+                None => println!("{} ceph {}", Colour::Green.paint("●"), issued.command),
+                Some(ref err) => println!(
+                    "{} ceph {}: {}",
+                    Colour::Red.paint("●"),
+                    issued.command,
+                    err.trim()
+                ),
+            }
+        }
+        let osds: Vec<String> = self.osds.iter().map(|id| id.to_string()).collect();
+        match self.outcome {
+            RemoveOutcome::Removed => println!(
+                "{} {:?} OSDs {} at epoch {}",
+                Colour::Green.paint("●"),
+                self.method,
+                osds.join(", "),
+                self.epoch.unwrap_or(self.checked_epoch)
+            ),
+            _ => println!(
+                "{} {:?} OSDs {} {:?}: {}",
+                Colour::Red.paint("●"),
+                self.method,
+                osds.join(", "),
+                self.outcome,
+                self.reason.as_deref().unwrap_or("")
+            ),
+        }
+    }
+}
+
+impl DiagMap {
+    // Why `osd_ids` cannot be destroyed or purged right now, if at all. Each
+    // OSD has to be removable, removing them together must not take a PG
+    // inactive as with `-s`, and the OSDs have to be down, ceph refuses
+    // otherwise.
+    fn removal_refusal(&self, osd_ids: &[i32]) -> Result<(Status, Option<String>), CSDError> {
+        let status = self.removal_status(Some(osd_ids))?;
+        if status != Status::Safe {
+            return Ok((status, Some(format!("the OSDs are {}", status))));
+        }
+        let simulation = self.simulate_removal(osd_ids)?;
+        if simulation.status != Status::Safe {
+            return Ok((
+                simulation.status,
+                Some(format!(
+                    "removing them together takes {} PGs inactive",
+                    simulation.inactive.len()
+                )),
+            ));
+        }
+        let up: Vec<String> = osd_ids
+            .iter()
+            .filter(|id| self.osd_map.osd(**id).is_some_and(|osd| osd.up != 0))
+            .map(|id| format!("osd.{}", id))
+            .collect();
+        if !up.is_empty() {
+            return Ok((status, Some(format!("{} still up, stop them first", up.join(", ")))));
+        }
+        Ok((status, None))
+    }
+}
+
+// Destroy or purge `osd_ids`. The OSDs must be removable and down. `confirm`
+// is asked before anything is issued, then the maps are fetched again and the
+// verdict is reached again on them, so a change since the check or while
+// waiting on `confirm` stops the removal. PG states can change without the
+// OSD map epoch moving, so the check is repeated whatever the epoch.
+// Commands are issued one OSD at a time and stop at the first failure.
+pub fn remove_osds<B, F>(
+    backend: &B,
+    osd_ids: &[i32],
+    method: RemoveMethod,
+    mut confirm: F,
+) -> Result<RemovalRecord, CSDError>
+where
+    B: CephBackend + ?Sized,
+    F: FnMut(&str) -> bool,
+{
+    let diag_map = DiagMap::from_backend(backend)?;
+    let (status, reason) = diag_map.removal_refusal(osd_ids)?;
+    let mut record = RemovalRecord {
+        osds: osd_ids.to_vec(),
+        method,
+        outcome: RemoveOutcome::Refused,
+        status,
+        reason,
+        checked_epoch: diag_map.osd_map.epoch,
+        epoch: None,
+        issued: Vec::new(),
+    };
+    if record.reason.is_some() {
+        return Ok(record);
+    }
+
+    let osds: Vec<String> = osd_ids.iter().map(|id| format!("osd.{}", id)).collect();
+    if !confirm(&format!("{:?} {}?", method, osds.join(", "))) {
+        record.outcome = RemoveOutcome::Declined;
+        record.reason = Some("not confirmed".to_string());
+        return Ok(record);
+    }
+
+    let diag_map = DiagMap::from_backend(backend)?;
+    record.epoch = Some(diag_map.osd_map.epoch);
+    let (status, reason) = diag_map.removal_refusal(osd_ids)?;
+    record.status = status;
+    if let Some(reason) = reason {
+        record.reason = Some(if diag_map.osd_map.epoch == record.checked_epoch {
+            format!("{} when checked again at epoch {}", reason, record.checked_epoch)
+        } else {
+            format!(
+                "{} at epoch {}, the check passed at epoch {}",
+                reason, diag_map.osd_map.epoch, record.checked_epoch
+            )
+        });
+        return Ok(record);
+    }
+
+    record.outcome = RemoveOutcome::Removed;
+    for osd_id in osd_ids {
+        let command = method.command(*osd_id);
+        let error = backend.mon_command(&command).err().map(|err| err.to_string());
+        let failed = error.is_some();
+        record.issued.push(IssuedCommand { command, error });
+        if failed {
+            record.outcome = RemoveOutcome::Failed;
+            record.reason = Some("a command failed".to_string());
+            break;
+        }
+    }
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use serde_json::Value;
+    use std::fs;
+
+    // The jewel `osd dump` at `epoch`, with `down` marked down
+    fn osd_dump(epoch: i32, down: &[i32]) -> String {
+        let mut osd_dump: Value =
+            serde_json::from_str(&fs::read_to_string("test/jewel/osd_dump_safe.json").unwrap()).unwrap();
+        osd_dump["epoch"] = Value::from(epoch);
+        for osd in osd_dump["osds"].as_array_mut().unwrap() {
+            if down.contains(&(osd["osd"].as_i64().unwrap() as i32)) {
+                osd["up"] = Value::from(0);
+            }
+        }
+        osd_dump.to_string()
+    }
+
+    fn jewel_cluster() -> MockBackend {
+        MockBackend::new()
+            .respond_from_file("osd crush dump", "test/jewel/osd_crush_dump.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_safe.json"))
+            .unwrap()
+            .respond("osd destroy osd.3 --yes-i-really-mean-it", "")
+            .respond("osd purge osd.3 --yes-i-really-mean-it", "")
+    }
+
+    #[test]
+    fn remove_destroy() {
+        // osd.3 is down and holds no PGs
+        let backend = jewel_cluster().respond("osd dump", &osd_dump(23, &[]));
+        let record = remove_osds(&backend, &[3], RemoveMethod::Destroy, |_| true).unwrap();
+        assert_eq!(record.outcome, RemoveOutcome::Removed);
+        assert_eq!(record.exit_status(), ExitStatus::SafeRm);
+        assert_eq!(record.issued.len(), 1);
+        assert_eq!(record.issued[0].command, "osd destroy osd.3 --yes-i-really-mean-it");
+        assert!(backend.calls().contains(&record.issued[0].command));
+        // The maps are fetched again after confirming
+        assert_eq!(backend.calls().iter().filter(|cmd| *cmd == "osd dump").count(), 2);
+    }
+
+    #[test]
+    fn remove_declined() {
+        let backend = jewel_cluster().respond("osd dump", &osd_dump(23, &[]));
+        let record = remove_osds(&backend, &[3], RemoveMethod::Purge, |_| false).unwrap();
+        assert_eq!(record.outcome, RemoveOutcome::Declined);
+        assert!(record.issued.is_empty());
+        assert!(!backend.calls().iter().any(|cmd| cmd.starts_with("osd purge")));
+    }
+
+    #[test]
+    fn remove_osd_up() {
+        let backend = jewel_cluster().respond("osd dump", &osd_dump(23, &[]));
+        let record = remove_osds(&backend, &[0], RemoveMethod::Purge, |_| true).unwrap();
+        assert_eq!(record.status, Status::Safe);
+        assert_eq!(record.outcome, RemoveOutcome::Refused);
+        assert_eq!(record.exit_status(), ExitStatus::NonSafeRm);
+        assert!(record.reason.unwrap().contains("osd.0 still up"));
+    }
+
+    #[test]
+    fn remove_together_unsafe() {
+        // osd.0 and osd.1 are each removable, but a size 3 pool with
+        // min_size 2 cannot lose both
+        let backend = jewel_cluster().respond("osd dump", &osd_dump(23, &[0, 1]));
+        let record = remove_osds(&backend, &[0, 1], RemoveMethod::Purge, |_| true).unwrap();
+        assert_eq!(record.outcome, RemoveOutcome::Refused);
+        assert_eq!(record.status, Status::NonSafe);
+        assert_eq!(record.exit_status(), ExitStatus::NonSafeRm);
+        assert_eq!(record.reason.unwrap(), "removing them together takes 64 PGs inactive");
+        assert!(!backend.calls().iter().any(|cmd| cmd.starts_with("osd purge")));
+        let backend = backend.respond("osd purge osd.1 --yes-i-really-mean-it", "");
+        let record = remove_osds(&backend, &[1], RemoveMethod::Purge, |_| true).unwrap();
+        assert_eq!(record.outcome, RemoveOutcome::Removed);
+    }
+
+    #[test]
+    fn remove_epoch_moved() {
+        // osd.0 is down and removable at epoch 24, by epoch 25 its PGs are
+        // peering again
+        let backend = MockBackend::new()
+            .respond_from_file("osd crush dump", "test/jewel/osd_crush_dump.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_safe.json"))
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_pending.json"))
+            .unwrap()
+            .respond("osd dump", &osd_dump(24, &[0]))
+            .respond("osd dump", &osd_dump(25, &[0]));
+        let record = remove_osds(&backend, &[0], RemoveMethod::Destroy, |_| true).unwrap();
+        assert_eq!(record.outcome, RemoveOutcome::Refused);
+        assert_eq!(record.checked_epoch, 24);
+        assert_eq!(record.epoch, Some(25));
+        assert_eq!(record.exit_status(), ExitStatus::from(record.status));
+        assert!(record.issued.is_empty());
+    }
+
+    #[test]
+    fn remove_same_epoch_changed() {
+        // The PGs start peering after the check without a new OSD map
+        let backend = MockBackend::new()
+            .respond_from_file("osd crush dump", "test/jewel/osd_crush_dump.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_safe.json"))
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_pending.json"))
+            .unwrap()
+            .respond("osd dump", &osd_dump(24, &[0]));
+        let record = remove_osds(&backend, &[0], RemoveMethod::Destroy, |_| true).unwrap();
+        assert_eq!(record.outcome, RemoveOutcome::Refused);
+        assert_eq!(record.epoch, Some(24));
+        assert!(record.reason.unwrap().contains("when checked again at epoch 24"));
+        assert!(record.issued.is_empty());
+    }
+
+    #[test]
+    fn remove_epoch_moved_still_safe() {
+        let backend = jewel_cluster()
+            .respond("osd dump", &osd_dump(23, &[]))
+            .respond("osd dump", &osd_dump(24, &[]));
+        let record = remove_osds(&backend, &[3], RemoveMethod::Purge, |_| true).unwrap();
+        assert_eq!(record.outcome, RemoveOutcome::Removed);
+        assert_eq!(record.epoch, Some(24));
+    }
+
+    #[test]
+    fn remove_command_fails() {
+        let backend = MockBackend::new()
+            .respond_from_file("osd crush dump", "test/jewel/osd_crush_dump.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_safe.json"))
+            .unwrap()
+            .respond("osd dump", &osd_dump(23, &[]));
+        let record = remove_osds(&backend, &[3], RemoveMethod::Destroy, |_| true).unwrap();
+        assert_eq!(record.outcome, RemoveOutcome::Failed);
+        assert_eq!(record.exit_status(), ExitStatus::Err);
+        assert!(record.issued[0].error.is_some());
+    }
+}
@@ -0,0 +1,345 @@
+use crate::backend::CephBackend;
+use crate::cache::{unix_time, CachedDiag, DiagCache};
+use crate::diag::{DiagMap, OsdExplain, Status};
+use crate::error::CSDError;
+use crate::http::{self, Request, Response};
+use crate::simulate::RemovalSimulation;
+
+use serde::Serialize;
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+// Every endpoint lives under this prefix, bumped when a response changes in
+// a way clients would notice
+pub const API_VERSION: &str = "v1";
+
+#[derive(Serialize, Debug)]
+struct ApiError {
+    error: String,
+}
+
+// `GET /v1/epoch`, which maps the answers come from
+#[derive(Serialize, Debug)]
+struct ApiEpoch<'a> {
+    fsid: &'a str,
+    epoch: i32,
+    // Seconds since the Unix epoch
+    collected_at: u64,
+    age_seconds: u64,
+}
+
+// `GET /v1/status`, the exhaustive check
+#[derive(Serialize, Debug)]
+struct ApiStatus {
+    epoch: i32,
+    status: Status,
+    removable: Vec<i32>,
+    pending: Vec<i32>,
+    not_removable: Vec<i32>,
+}
+
+// `GET /v1/osds`, every OSD with the PGs behind its status
+#[derive(Serialize, Debug)]
+struct ApiOsds {
+    epoch: i32,
+    status: Status,
+    osds: Vec<OsdExplain>,
+}
+
+// `GET /v1/osds/ID`, a single OSD
+#[derive(Serialize, Debug)]
+struct ApiOsd {
+    epoch: i32,
+    #[serde(flatten)]
+    osd: OsdExplain,
+}
+
+// `GET /v1/whatif?osds=ID,ID`, whether the OSDs are removable now and what
+// removing all of them at once would do to their PGs
+#[derive(Serialize, Debug)]
+struct ApiWhatIf {
+    epoch: i32,
+    osds: Vec<i32>,
+    // The worst of `removable` and the simulation's status
+    status: Status,
+    removable: Status,
+    simulation: RemovalSimulation,
+}
+
+fn json<T: Serialize>(status: u16, body: &T) -> Response {
+    match serde_json::to_string(body) {
+        Ok(body) => Response::json(status, body),
+        Err(err) => error(500, err.to_string()),
+    }
+}
+
+fn error(status: u16, error: String) -> Response {
+    Response::json(
+        status,
+        serde_json::to_string(&ApiError { error }).unwrap_or_default(),
+    )
+}
+
+// Unknown OSDs are a 404, anything else a 500
+fn diag_error(err: CSDError) -> Response {
+    match err {
+        CSDError::UnknownOsd(_) => error(404, err.to_string()),
+        err => error(500, err.to_string()),
+    }
+}
+
+// A comma separated list of OSD ids, `3` or `osd.3`
+fn parse_osd_ids(arg: &str) -> Result<Vec<i32>, String> {
+    let ids = arg
+        .replace("%2C", ",")
+        .replace("%2c", ",")
+        .split(',')
+        .map(|id| id.trim())
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.trim_start_matches("osd.")
+                .parse::<i32>()
+                .map_err(|_| format!("Invalid OSD id `{}`", id))
+        })
+        .collect::<Result<Vec<i32>, String>>()?;
+    if ids.is_empty() {
+        return Err("No OSD ids given".to_string());
+    }
+    Ok(ids)
+}
+
+// The value of `key` in a query string such as `osds=3,7`
+fn query_param<'a>(request: &'a Request, key: &str) -> Option<&'a str> {
+    request.query.as_ref().and_then(|query| {
+        query.split('&').find_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name == key => Some(value),
+                _ => None,
+            }
+        })
+    })
+}
+
+fn status(diag_map: &DiagMap) -> Response {
+    let mut body = ApiStatus {
+        epoch: diag_map.osd_map.epoch,
+        status: match diag_map.removal_status(None) {
+            Ok(status) => status,
+            Err(err) => return diag_error(err),
+        },
+        removable: Vec::new(),
+        pending: Vec::new(),
+        not_removable: Vec::new(),
+    };
+    for (osd_id, status) in diag_map.osd_statuses() {
+        match status {
+            Status::Safe => body.removable.push(osd_id),
+            Status::Unknown => body.pending.push(osd_id),
+            Status::NonSafe => body.not_removable.push(osd_id),
+        }
+    }
+    json(200, &body)
+}
+
+fn osds(diag_map: &DiagMap) -> Response {
+    match diag_map.cluster_explain(None) {
+        Ok((status, explain)) => json(
+            200,
+            &ApiOsds {
+                epoch: diag_map.osd_map.epoch,
+                status,
+                osds: explain.osds,
+            },
+        ),
+        Err(err) => diag_error(err),
+    }
+}
+
+fn osd(diag_map: &DiagMap, id: &str) -> Response {
+    let osd_ids = match parse_osd_ids(id) {
+        Ok(ref ids) if ids.len() == 1 => ids.clone(),
+        Ok(_) => return error(400, "Ask for a single OSD, or use /v1/whatif".to_string()),
+        Err(err) => return error(400, err),
+    };
+    match diag_map.cluster_explain(Some(&osd_ids)) {
+        Ok((_, explain)) => match explain.osds.into_iter().next() {
+            Some(osd) => json(
+                200,
+                &ApiOsd {
+                    epoch: diag_map.osd_map.epoch,
+                    osd,
+                },
+            ),
+            None => error(404, format!("No OSD {}", id)),
+        },
+        Err(err) => diag_error(err),
+    }
+}
+
+fn what_if(diag_map: &DiagMap, request: &Request) -> Response {
+    let osd_ids = match query_param(request, "osds").map(parse_osd_ids) {
+        Some(Ok(ids)) => ids,
+        Some(Err(err)) => return error(400, err),
+        None => return error(400, "Give the OSDs as ?osds=ID,ID".to_string()),
+    };
+    let checked = diag_map.removal_status(Some(&osd_ids)).and_then(|removable| {
+        Ok((removable, diag_map.simulate_removal(&osd_ids)?))
+    });
+    match checked {
+        Ok((removable, simulation)) => json(
+            200,
+            &ApiWhatIf {
+                epoch: diag_map.osd_map.epoch,
+                osds: osd_ids,
+                status: removable.max(simulation.status),
+                removable,
+                simulation,
+            },
+        ),
+        Err(err) => diag_error(err),
+    }
+}
+
+// Route `request` to its endpoint, answered from the latest maps in `cache`
+fn api_response(cache: &DiagCache, request: &Request) -> Response {
+    let path = match request
+        .path
+        .strip_prefix('/')
+        .and_then(|path| path.strip_prefix(API_VERSION))
+    {
+        Some(path) => path.trim_end_matches('/'),
+        None => return error(404, format!("Not found, the API is under /{}", API_VERSION)),
+    };
+    let cached = match cache.get() {
+        Some(cached) => cached,
+        None => {
+            let err = cache
+                .last_error()
+                .unwrap_or_else(|| "No maps fetched yet".to_string());
+            return error(503, err);
+        }
+    };
+    let CachedDiag {
+        ref diag_map,
+        collected_at,
+    } = *cached;
+    match path {
+        "/epoch" => json(
+            200,
+            &ApiEpoch {
+                fsid: &diag_map.osd_map.fsid,
+                epoch: diag_map.osd_map.epoch,
+                collected_at,
+                age_seconds: unix_time().saturating_sub(collected_at),
+            },
+        ),
+        "/status" => status(diag_map),
+        "/osds" => osds(diag_map),
+        "/whatif" => what_if(diag_map, request),
+        _ => match path.strip_prefix("/osds/") {
+            Some(id) => osd(diag_map, id),
+            None => error(404, format!("No endpoint {}", request.path)),
+        },
+    }
+}
+
+// Answer the JSON API on `addr` from maps fetched from `backend` every
+// `interval`. Only returns if `addr` cannot be bound.
+pub fn serve<B: CephBackend + ?Sized>(
+    backend: &B,
+    addr: &str,
+    interval: Duration,
+) -> Result<(), CSDError> {
+    let listener = TcpListener::bind(addr)?;
+    let cache = DiagCache::new();
+    let served = cache.clone();
+    thread::spawn(move || http::serve(listener, move |request| api_response(&served, request)));
+    cache.refresh_every(backend, interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Snapshot;
+    use serde_json::Value;
+
+    fn luminous_racks() -> DiagCache {
+        let cache = DiagCache::new();
+        cache
+            .refresh(&Snapshot::open("test/luminous/snapshot_racks.json").unwrap())
+            .unwrap();
+        cache
+    }
+
+    fn get(cache: &DiagCache, target: &str) -> (u16, Value) {
+        let (path, query) = match target.find('?') {
+            Some(index) => (&target[..index], Some(target[index + 1..].to_string())),
+            None => (target, None),
+        };
+        let response = api_response(
+            cache,
+            &Request {
+                method: "GET".to_string(),
+                path: path.to_string(),
+                query,
+            },
+        );
+        assert_eq!(response.content_type, "application/json");
+        (response.status, serde_json::from_str(&response.body).unwrap())
+    }
+
+    #[test]
+    fn serve_epoch_and_status() {
+        let cache = luminous_racks();
+        let (code, epoch) = get(&cache, "/v1/epoch");
+        assert_eq!(code, 200);
+        assert_eq!(epoch["epoch"], 311);
+        assert_eq!(epoch["collected_at"], 1543859445);
+        let (code, status) = get(&cache, "/v1/status");
+        assert_eq!(code, 200);
+        assert_eq!(status["status"], "Safe");
+        assert!(status["removable"].as_array().unwrap().contains(&Value::from(5)));
+    }
+
+    #[test]
+    fn serve_osds() {
+        let cache = luminous_racks();
+        let (code, osds) = get(&cache, "/v1/osds");
+        assert_eq!(code, 200);
+        assert!(!osds["osds"].as_array().unwrap().is_empty());
+        let (code, osd) = get(&cache, "/v1/osds/osd.5");
+        assert_eq!(code, 200);
+        assert_eq!(osd["osd_id"], 5);
+        assert_eq!(osd["status"], "Safe");
+        assert_eq!(osd["epoch"], 311);
+        let (code, missing) = get(&cache, "/v1/osds/99");
+        assert_eq!(code, 404);
+        assert!(missing["error"].as_str().unwrap().contains("99"));
+        assert_eq!(get(&cache, "/v1/osds/five").0, 400);
+    }
+
+    #[test]
+    fn serve_what_if() {
+        let cache = luminous_racks();
+        let (code, what_if) = get(&cache, "/v1/whatif?osds=5");
+        assert_eq!(code, 200);
+        assert_eq!(what_if["removable"], "Safe");
+        assert_eq!(what_if["simulation"]["osds"], serde_json::json!([5]));
+        // Both OSDs are in the acting set of PG 1.0 along with one other
+        let (code, what_if) = get(&cache, "/v1/whatif?osds=0%2C2");
+        assert_eq!(code, 200);
+        assert_eq!(what_if["status"], "NonSafe");
+        assert!(!what_if["simulation"]["inactive"].as_array().unwrap().is_empty());
+        assert_eq!(get(&cache, "/v1/whatif").0, 400);
+    }
+
+    #[test]
+    fn serve_errors() {
+        assert_eq!(get(&DiagCache::new(), "/v1/status").0, 503);
+        let cache = luminous_racks();
+        assert_eq!(get(&cache, "/v2/status").0, 404);
+        assert_eq!(get(&cache, "/v1/other").0, 404);
+    }
+}
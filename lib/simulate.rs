@@ -0,0 +1,775 @@
+use ansi_term::Colour;
+use crate::crushmap::CrushMap;
+use crate::diag::{DiagMap, Format, Status};
+use crate::error::CSDError;
+use crate::mapper::Mapper;
+use crate::osdmap::Pools;
+use crate::pgmap::{slot_osds, PgStats, ShardSlot};
+
+// What happens to a PG once the simulated OSDs are gone. `Inactive` PGs fall
+// below their pool's `min_size` and stop serving I/O, `Degraded` PGs stay
+// active but are short of `size` copies until recovery.
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PgOutcome {
+    Inactive,
+    Degraded,
+}
+
+// A PG that loses at least one member of its acting set. Erasure coded PGs
+// keep their shard positions, with the removed OSDs' slots left empty.
+#[derive(Serialize, Debug, Clone)]
+pub struct PgImpact {
+    pub pg_id: String,
+    pub pg_state: String,
+    pub pool: String,
+    pub size: i32,
+    pub min_size: i32,
+    // Members needed to stay active, `min_size` or the erasure code `k`
+    pub required: i32,
+    pub acting: Vec<ShardSlot>,
+    pub up: Vec<ShardSlot>,
+    pub outcome: PgOutcome,
+}
+
+impl PgImpact {
+    // OSDs left in the acting set
+    pub fn members(&self) -> usize {
+        slot_osds(&self.acting).count()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RemovalSimulation {
+    pub osds: Vec<i32>,
+    pub status: Status,
+    pub inactive: Vec<PgImpact>,
+    pub degraded: Vec<PgImpact>,
+}
+
+impl RemovalSimulation {
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => self.print_pretty(),
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+
+    fn print_pretty(&self) {
+        let osds: Vec<String> = self.osds.iter().map(|id| id.to_string()).collect();
+        println!("Simulated removal of OSDs {}:", osds.join(", "));
+        for pg in &self.inactive {
+            println!(
+                "{} {} ({}): inactive, {} of {} required left",
+                Colour::Red.paint("●"),
+                pg.pg_id,
+                pg.pool,
+                pg.members(),
+                pg.required
+            );
+        }
+        for pg in &self.degraded {
+            println!(
+                "{} {} ({}): degraded, {} of size {} left",
+                Colour::Yellow.paint("●"),
+                pg.pg_id,
+                pg.pool,
+                pg.members(),
+                pg.size
+            );
+        }
+        let colour = match self.status {
+            Status::Safe => Colour::Green,
+            Status::Unknown => Colour::Yellow,
+            Status::NonSafe => Colour::Red,
+        };
+        println!(
+            "{} {}: {} PGs inactive, {} PGs degraded",
+            colour.paint("●"),
+            self.status,
+            self.inactive.len(),
+            self.degraded.len()
+        );
+    }
+}
+
+// A PG whose `up` set CRUSH can no longer fill once the OSDs are weighted to
+// zero. `up` is the set as currently reported, `mapped` the recomputed one.
+#[derive(Serialize, Debug, Clone)]
+pub struct PgPlacement {
+    pub pg_id: String,
+    pub pool: String,
+    pub size: i32,
+    pub min_size: i32,
+    pub up: Vec<ShardSlot>,
+    pub mapped: Vec<ShardSlot>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlacementSimulation {
+    pub osds: Vec<i32>,
+    pub status: Status,
+    // PGs that would move to a different set of OSDs
+    pub remapped: usize,
+    pub unmappable: Vec<PgPlacement>,
+}
+
+impl PlacementSimulation {
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => self.print_pretty(),
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+
+    fn print_pretty(&self) {
+        let osds: Vec<String> = self.osds.iter().map(|id| id.to_string()).collect();
+        println!("Recomputed placement without OSDs {}:", osds.join(", "));
+        for pg in &self.unmappable {
+            let mapped = slot_osds(&pg.mapped).count();
+            println!(
+                "{} {} ({}): only {} of size {} can be placed",
+                Colour::Red.paint("●"),
+                pg.pg_id,
+                pg.pool,
+                mapped,
+                pg.size
+            );
+        }
+        let colour = match self.status {
+            Status::Safe => Colour::Green,
+            Status::Unknown => Colour::Yellow,
+            Status::NonSafe => Colour::Red,
+        };
+        println!(
+            "{} {}: {} PGs remapped, {} PGs cannot be fully placed",
+            colour.paint("●"),
+            self.status,
+            self.remapped,
+            self.unmappable.len()
+        );
+    }
+}
+
+// A pool whose CRUSH rule spreads replicas across `failure_domain` buckets,
+// and how many of those still hold an OSD after the removal
+#[derive(Serialize, Debug, Clone)]
+pub struct PoolDomains {
+    pub pool: String,
+    pub rule: String,
+    pub failure_domain: String,
+    pub size: i32,
+    pub min_size: i32,
+    pub domains: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BucketRemoval {
+    pub bucket: String,
+    pub osds: Vec<i32>,
+    pub status: Status,
+    // Pools that cannot find `size` failure domains any more
+    pub pools: Vec<PoolDomains>,
+    // PGs with every member of their acting set under the bucket
+    pub lost: Vec<PgImpact>,
+    pub inactive: Vec<PgImpact>,
+}
+
+impl BucketRemoval {
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => self.print_pretty(),
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+
+    fn print_pretty(&self) {
+        let osds: Vec<String> = self.osds.iter().map(|id| id.to_string()).collect();
+        println!(
+            "Simulated removal of {} (OSDs {}):",
+            self.bucket,
+            osds.join(", ")
+        );
+        for pool in &self.pools {
+            println!(
+                "{} {}: only {} {} left for size {}",
+                Colour::Red.paint("●"),
+                pool.pool,
+                pool.domains,
+                pool.failure_domain,
+                pool.size
+            );
+        }
+        for pg in &self.lost {
+            println!(
+                "{} {} ({}): every copy is under {}",
+                Colour::Red.paint("●"),
+                pg.pg_id,
+                pg.pool,
+                self.bucket
+            );
+        }
+        for pg in &self.inactive {
+            println!(
+                "{} {} ({}): inactive, {} of {} required left",
+                Colour::Red.paint("●"),
+                pg.pg_id,
+                pg.pool,
+                pg.members(),
+                pg.required
+            );
+        }
+        let colour = match self.status {
+            Status::Safe => Colour::Green,
+            Status::Unknown => Colour::Yellow,
+            Status::NonSafe => Colour::Red,
+        };
+        println!(
+            "{} {}: {} pools short of failure domains, {} PGs lost, {} PGs inactive",
+            colour.paint("●"),
+            self.status,
+            self.pools.len(),
+            self.lost.len(),
+            self.inactive.len()
+        );
+    }
+}
+
+// A PG with its `up` set recomputed by CRUSH
+pub(crate) struct PgRemap<'a> {
+    pub(crate) pg_stat: &'a PgStats,
+    pub(crate) pool: &'a Pools,
+    pub(crate) mapped: Vec<ShardSlot>,
+}
+
+impl<'a> PgRemap<'a> {
+    // OSDs that would have to backfill a copy of the PG
+    pub(crate) fn targets(&self) -> Vec<i32> {
+        slot_osds(&self.mapped)
+            .filter(|osd| !self.pg_stat.up_osds().any(|up| up == *osd))
+            .collect()
+    }
+}
+
+impl DiagMap {
+    // Run CRUSH for every PG with the OSDs in `osd_ids` weighted to zero
+    pub(crate) fn remap_pgs(
+        &self,
+        crush_map: &CrushMap,
+        osd_ids: &[i32],
+    ) -> Result<Vec<PgRemap<'_>>, CSDError> {
+        let mapper = Mapper::new(crush_map)?;
+        let mut weights = self.osd_map.crush_weights();
+        for osd_id in osd_ids {
+            if let Some(weight) = weights.get_mut(*osd_id as usize) {
+                *weight = 0;
+            }
+        }
+
+        let mut remaps = Vec::new();
+        for pg_stat in &self.pg_map.pg_stats {
+            let pool = match pg_stat.pool_id().and_then(|id| self.osd_map.pool(id)) {
+                Some(pool) => pool,
+                None => {
+                    warn!("No pool found for PG {}", pg_stat.pgid);
+                    continue;
+                }
+            };
+            let ps = match pg_stat.ps() {
+                Some(ps) => ps,
+                None => continue,
+            };
+            let mapped = mapper
+                .pg_up(&self.osd_map, pool, ps, &weights)
+                .ok_or_else(|| {
+                    CSDError::CrushError(format!("no CRUSH rule for pool {}", pool.pool_name))
+                })?;
+            remaps.push(PgRemap {
+                pg_stat,
+                pool,
+                mapped,
+            });
+        }
+        Ok(remaps)
+    }
+
+    // Whether `osd_ids` can all go at once. Each one has to be removable, and
+    // removing them together must not take any PG inactive.
+    pub fn joint_removal_status(&self, osd_ids: &[i32]) -> Result<Status, CSDError> {
+        let status = self.removal_status(Some(osd_ids))?;
+        Ok(status.max(self.simulate_removal(osd_ids)?.status))
+    }
+
+    // Remove every OSD in `osd_ids` from each PG's `acting` and `up` sets at
+    // the same time and check what is left against the PG's pool. Removal is
+    // only safe when no PG drops below its pool's `min_size`, or for erasure
+    // coded pools below the `k` shards needed to rebuild its objects.
+    pub fn simulate_removal(&self, osd_ids: &[i32]) -> Result<RemovalSimulation, CSDError> {
+        self.check_osds(osd_ids)?;
+
+        let mut simulation = RemovalSimulation {
+            osds: osd_ids.to_vec(),
+            status: Status::Safe,
+            inactive: Vec::new(),
+            degraded: Vec::new(),
+        };
+
+        for pg_stat in &self.pg_map.pg_stats {
+            if !pg_stat.acting_osds().any(|osd| osd_ids.contains(&osd))
+                && !pg_stat.up_osds().any(|osd| osd_ids.contains(&osd))
+            {
+                continue;
+            }
+            let pool = match pg_stat.pool_id().and_then(|id| self.osd_map.pool(id)) {
+                Some(pool) => pool,
+                None => {
+                    warn!("No pool found for PG {}", pg_stat.pgid);
+                    continue;
+                }
+            };
+            let remaining = |set: &[ShardSlot]| -> Vec<ShardSlot> {
+                set.iter()
+                    .filter_map(|slot| match slot.osd() {
+                        Some(osd) if osd_ids.contains(&osd) => {
+                            if pool.is_erasure() {
+                                Some(ShardSlot::None)
+                            } else {
+                                None
+                            }
+                        }
+                        _ => Some(*slot),
+                    })
+                    .collect()
+            };
+            let acting = remaining(&pg_stat.acting);
+            let members = slot_osds(&acting).count() as i32;
+            let required = self.required_members(pool);
+            let outcome = if members < required {
+                PgOutcome::Inactive
+            } else if members < pool.size {
+                PgOutcome::Degraded
+            } else {
+                continue;
+            };
+            let impact = PgImpact {
+                pg_id: pg_stat.pgid.clone(),
+                pg_state: pg_stat.state.clone(),
+                pool: pool.pool_name.clone(),
+                size: pool.size,
+                min_size: pool.min_size,
+                required,
+                up: remaining(&pg_stat.up),
+                acting,
+                outcome,
+            };
+            match outcome {
+                PgOutcome::Inactive => simulation.inactive.push(impact),
+                PgOutcome::Degraded => simulation.degraded.push(impact),
+            }
+        }
+
+        if !simulation.inactive.is_empty() {
+            simulation.status = Status::NonSafe;
+        }
+        Ok(simulation)
+    }
+
+    // Run CRUSH for every PG with the OSDs in `osd_ids` weighted to zero, as
+    // if they had been marked out. A PG that cannot be given `size` OSDs any
+    // more would stay degraded after recovery, which makes the removal unsafe.
+    pub fn simulate_placement(&self, osd_ids: &[i32]) -> Result<PlacementSimulation, CSDError> {
+        self.check_osds(osd_ids)?;
+        let crush_map = self.crush_map()?;
+        let mut simulation = PlacementSimulation {
+            osds: osd_ids.to_vec(),
+            status: Status::Safe,
+            remapped: 0,
+            unmappable: Vec::new(),
+        };
+        for remap in self.remap_pgs(crush_map, osd_ids)? {
+            if remap.mapped != remap.pg_stat.up {
+                simulation.remapped += 1;
+            }
+            let placed = slot_osds(&remap.mapped).count();
+            if (placed as i32) < remap.pool.size {
+                simulation.unmappable.push(PgPlacement {
+                    pg_id: remap.pg_stat.pgid.clone(),
+                    pool: remap.pool.pool_name.clone(),
+                    size: remap.pool.size,
+                    min_size: remap.pool.min_size,
+                    up: remap.pg_stat.up.clone(),
+                    mapped: remap.mapped,
+                });
+            }
+        }
+
+        if !simulation.unmappable.is_empty() {
+            simulation.status = Status::NonSafe;
+        }
+        Ok(simulation)
+    }
+
+    // Print the result of `simulate_placement` based on `format`
+    pub fn placement_diag(self, osd_ids: &[i32], format: Format) -> Result<Status, CSDError> {
+        let simulation = self.simulate_placement(osd_ids)?;
+        simulation.print(format);
+        Ok(simulation.status)
+    }
+
+    // The OSDs in the OSD map under the CRUSH bucket `name`
+    pub fn bucket_osds(&self, name: &str) -> Result<Vec<i32>, CSDError> {
+        let crush_map = self.crush_map()?;
+        let bucket = crush_map
+            .bucket_by_name(name)
+            .ok_or_else(|| CSDError::UnknownBucket(name.to_string()))?;
+        Ok(crush_map
+            .osds_under(bucket.id)
+            .into_iter()
+            .filter(|osd_id| self.osd_map.osd(*osd_id).is_some())
+            .collect())
+    }
+
+    // Remove every OSD under the CRUSH bucket `name`, e.g. a host or a rack,
+    // at once. On top of `simulate_removal` every pool's rule is checked for
+    // whether enough of its failure domains still hold an OSD to place `size`
+    // replicas. Only OSDs that exist, are up and have a CRUSH weight count.
+    pub fn simulate_bucket_removal(&self, name: &str) -> Result<BucketRemoval, CSDError> {
+        let crush_map = self.crush_map()?;
+        let osd_ids = self.bucket_osds(name)?;
+        let weights = self.osd_map.crush_weights();
+        let survives = |osd_id: &i32| {
+            !osd_ids.contains(osd_id)
+                && weights.get(*osd_id as usize).is_some_and(|w| *w > 0)
+                && self
+                    .osd_map
+                    .osd(*osd_id)
+                    .is_some_and(|osd| osd.exists() && osd.up != 0)
+        };
+
+        let mut pools = Vec::new();
+        for pool in &self.osd_map.pools {
+            let rule = match crush_map.pool_rule(pool) {
+                Some(rule) => rule,
+                None => {
+                    warn!("No CRUSH rule found for pool {}", pool.pool_name);
+                    continue;
+                }
+            };
+            let failure_domain = rule.failure_domain().unwrap_or("osd");
+            let mut domains = 0;
+            for root in rule.steps.iter().filter_map(|step| match step.op.as_ref() {
+                "take" => step.item,
+                _ => None,
+            }) {
+                domains += if failure_domain == "osd" {
+                    crush_map.osds_under(root).iter().filter(|osd| survives(osd)).count()
+                } else {
+                    crush_map
+                        .buckets_under(root, failure_domain)
+                        .iter()
+                        .filter(|domain| crush_map.osds_under(domain.id).iter().any(&survives))
+                        .count()
+                };
+            }
+            if (domains as i32) < pool.size {
+                pools.push(PoolDomains {
+                    pool: pool.pool_name.clone(),
+                    rule: rule.rule_name.clone(),
+                    failure_domain: failure_domain.to_string(),
+                    size: pool.size,
+                    min_size: pool.min_size,
+                    domains,
+                });
+            }
+        }
+
+        let simulation = self.simulate_removal(&osd_ids)?;
+        let (lost, inactive) = simulation
+            .inactive
+            .into_iter()
+            .partition(|pg: &PgImpact| pg.members() == 0);
+        let mut removal = BucketRemoval {
+            bucket: name.to_string(),
+            osds: osd_ids,
+            status: simulation.status,
+            pools,
+            lost,
+            inactive,
+        };
+        if !removal.pools.is_empty() {
+            removal.status = Status::NonSafe;
+        }
+        Ok(removal)
+    }
+
+    // Print the result of `simulate_bucket_removal` based on `format`
+    pub fn bucket_diag(self, name: &str, format: Format) -> Result<Status, CSDError> {
+        let removal = self.simulate_bucket_removal(name)?;
+        removal.print(format);
+        Ok(removal.status)
+    }
+
+    // Print the result of `simulate_removal` based on `format`
+    pub fn simulate_diag(self, osd_ids: &[i32], format: Format) -> Result<Status, CSDError> {
+        let simulation = self.simulate_removal(osd_ids)?;
+        simulation.print(format);
+        Ok(simulation.status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from::FromFile;
+    use crate::osdmap::{ErasureCodeProfile, OsdMap};
+    use std::collections::BTreeMap;
+    use crate::pgmap::PGMap;
+
+    fn jewel_safe() -> DiagMap {
+        DiagMap {
+            pg_map: PGMap::from_file("test/jewel/pg_dump_safe.json").unwrap(),
+            osd_map: OsdMap::from_file("test/jewel/osd_dump_safe.json").unwrap(),
+            crush_map: Err(CSDError::CrushError("not recorded".to_string())),
+            ec_profiles: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn simulate_single_osd_degrades() {
+        let simulation = jewel_safe().simulate_removal(&[0]).unwrap();
+        assert_eq!(simulation.status, Status::Safe);
+        assert!(simulation.inactive.is_empty());
+        // Every PG in the pool has a copy on osd.0
+        assert_eq!(simulation.degraded.len(), 64);
+    }
+
+    #[test]
+    fn simulate_shared_osds_inactive() {
+        let simulation = jewel_safe().simulate_removal(&[0, 1]).unwrap();
+        assert_eq!(simulation.status, Status::NonSafe);
+        assert_eq!(simulation.inactive.len(), 64);
+        assert_eq!(simulation.inactive[0].acting, vec![ShardSlot::Osd(2)]);
+    }
+
+    #[test]
+    fn simulate_osd_without_pgs() {
+        let simulation = jewel_safe().simulate_removal(&[3]).unwrap();
+        assert_eq!(simulation.status, Status::Safe);
+        assert!(simulation.inactive.is_empty());
+        assert!(simulation.degraded.is_empty());
+    }
+
+    fn luminous_racks() -> DiagMap {
+        use crate::crushmap::CrushMap;
+        let mut ec_profiles = BTreeMap::new();
+        ec_profiles.insert(
+            "ec22".to_string(),
+            ErasureCodeProfile::from_file("test/luminous/erasure_code_profile_ec22.json").unwrap(),
+        );
+        DiagMap {
+            pg_map: PGMap::from_file("test/luminous/pg_dump_racks.json").unwrap(),
+            osd_map: OsdMap::from_file("test/luminous/osd_dump_racks.json").unwrap(),
+            crush_map: Ok(CrushMap::from_file("test/luminous/osd_crush_dump_racks.json").unwrap()),
+            ec_profiles,
+        }
+    }
+
+    // The racks fixtures were not recorded from a cluster, this only checks
+    // the simulation agrees with itself. `simulate_placement_jewel_unchanged`
+    // checks it against ceph.
+    #[test]
+    fn simulate_placement_unchanged() {
+        let simulation = luminous_racks().simulate_placement(&[]).unwrap();
+        assert_eq!(simulation.status, Status::Safe);
+        assert_eq!(simulation.remapped, 0);
+    }
+
+    #[test]
+    fn simulate_placement_single_osd() {
+        let diag_map = luminous_racks();
+        let simulation = diag_map.simulate_placement(&[3]).unwrap();
+        assert_eq!(simulation.status, Status::Safe);
+        let on_osd = diag_map
+            .pg_map
+            .pg_stats
+            .iter()
+            .filter(|pg_stat| pg_stat.up.contains(&ShardSlot::Osd(3)))
+            .count();
+        assert_eq!(simulation.remapped, on_osd);
+    }
+
+    #[test]
+    fn simulate_placement_whole_host() {
+        // Three hosts are left for `rbd` and both racks for `backups`, but
+        // `ecpool` needs a host for each of its four shards
+        let simulation = luminous_racks().simulate_placement(&[6, 7]).unwrap();
+        assert_eq!(simulation.status, Status::NonSafe);
+        assert_eq!(simulation.unmappable.len(), 16);
+        assert!(simulation.unmappable.iter().all(|pg| pg.pool == "ecpool"));
+    }
+
+    #[test]
+    fn simulate_placement_whole_rack() {
+        // Two hosts in one rack cannot hold three `rbd` replicas, four
+        // `ecpool` shards or two `backups` replicas in distinct racks
+        let simulation = luminous_racks().simulate_placement(&[4, 5, 6, 7]).unwrap();
+        assert_eq!(simulation.status, Status::NonSafe);
+        assert_eq!(simulation.unmappable.len(), 64);
+        let backups = simulation.unmappable.iter().find(|pg| pg.pool == "backups").unwrap();
+        assert_eq!(backups.mapped.len(), 1);
+    }
+
+    #[test]
+    fn simulate_placement_jewel_unchanged() {
+        use crate::crushmap::CrushMap;
+        let simulation = DiagMap {
+            crush_map: Ok(CrushMap::from_file("test/jewel/osd_crush_dump.json").unwrap()),
+            ..jewel_safe()
+        }.simulate_placement(&[])
+            .unwrap();
+        assert_eq!(simulation.status, Status::Safe);
+        assert_eq!(simulation.remapped, 0);
+    }
+
+    #[test]
+    fn simulate_placement_jewel_host() {
+        use crate::crushmap::CrushMap;
+        // One OSD per host and only three hosts with an OSD in, a size 3 pool
+        // needs all of them
+        let simulation = DiagMap {
+            crush_map: Ok(CrushMap::from_file("test/jewel/osd_crush_dump.json").unwrap()),
+            ..jewel_safe()
+        }.simulate_placement(&[0])
+            .unwrap();
+        assert_eq!(simulation.status, Status::NonSafe);
+        assert_eq!(simulation.unmappable.len(), 64);
+    }
+
+    #[test]
+    fn simulate_bucket_host() {
+        let removal = luminous_racks().simulate_bucket_removal("stor-04").unwrap();
+        assert_eq!(removal.osds, vec![6, 7]);
+        assert_eq!(removal.status, Status::NonSafe);
+        assert_eq!(removal.pools.len(), 1);
+        assert_eq!(removal.pools[0].pool, "ecpool");
+        assert_eq!(removal.pools[0].domains, 3);
+        // The PGs stay active on their remaining shards
+        assert!(removal.inactive.is_empty());
+    }
+
+    #[test]
+    fn simulate_bucket_host_another_down() {
+        // With stor-03 down as well, only the two hosts of rack1 are left
+        let mut diag_map = luminous_racks();
+        for osd in diag_map.osd_map.osds.iter_mut().filter(|osd| osd.osd == 4 || osd.osd == 5) {
+            osd.up = 0;
+        }
+        let removal = diag_map.simulate_bucket_removal("stor-04").unwrap();
+        assert_eq!(removal.status, Status::NonSafe);
+        let domains: Vec<(&str, usize)> = removal
+            .pools
+            .iter()
+            .map(|pool| (pool.pool.as_str(), pool.domains))
+            .collect();
+        assert_eq!(domains, vec![("rbd", 2), ("backups", 1), ("ecpool", 2)]);
+    }
+
+    #[test]
+    fn simulate_bucket_rack() {
+        let removal = luminous_racks().simulate_bucket_removal("rack2").unwrap();
+        assert_eq!(removal.osds, vec![4, 5, 6, 7]);
+        assert_eq!(removal.status, Status::NonSafe);
+        let domains: Vec<(&str, &str, usize)> = removal
+            .pools
+            .iter()
+            .map(|pool| (pool.pool.as_str(), pool.failure_domain.as_str(), pool.domains))
+            .collect();
+        assert_eq!(
+            domains,
+            vec![("rbd", "host", 2), ("backups", "rack", 1), ("ecpool", "host", 2)]
+        );
+        assert!(removal.lost.is_empty());
+    }
+
+    #[test]
+    fn simulate_bucket_jewel_root() {
+        use crate::crushmap::CrushMap;
+        let removal = DiagMap {
+            crush_map: Ok(CrushMap::from_file("test/jewel/osd_crush_dump.json").unwrap()),
+            ..jewel_safe()
+        }.simulate_bucket_removal("default")
+            .unwrap();
+        assert_eq!(removal.status, Status::NonSafe);
+        assert_eq!(removal.lost.len(), 64);
+        assert_eq!(removal.pools[0].domains, 0);
+    }
+
+    #[test]
+    fn simulate_bucket_unknown() {
+        match luminous_racks().simulate_bucket_removal("stor-09") {
+            Err(CSDError::UnknownBucket(name)) => assert_eq!(name, "stor-09"),
+            _ => panic!("expected an unknown bucket error"),
+        }
+    }
+
+    #[test]
+    fn simulate_erasure_single_host() {
+        // A 2+2 pool keeps 3 of its 4 shards, enough for `min_size` 3
+        let simulation = luminous_racks().simulate_removal(&[0, 1]).unwrap();
+        assert!(simulation.inactive.iter().all(|pg| pg.pool != "ecpool"));
+        let degraded = simulation.degraded.iter().filter(|pg| pg.pool == "ecpool").count();
+        assert!(degraded > 0);
+    }
+
+    #[test]
+    fn simulate_erasure_two_hosts() {
+        // Two shards are still `k` but below `min_size`, the PGs stop serving I/O
+        let simulation = luminous_racks().simulate_removal(&[0, 1, 2, 3]).unwrap();
+        assert_eq!(simulation.status, Status::NonSafe);
+        let ecpool: Vec<&PgImpact> = simulation
+            .inactive
+            .iter()
+            .filter(|pg| pg.pool == "ecpool")
+            .collect();
+        assert_eq!(ecpool.len(), 16);
+        assert!(ecpool.iter().all(|pg| pg.required == 3 && pg.members() == 2));
+        // The shards keep their positions
+        assert!(ecpool.iter().all(|pg| pg.acting.len() == 4));
+    }
+
+    #[test]
+    fn simulate_erasure_k_above_min_size() {
+        // A pool with `min_size` lowered below `k` still needs `k` shards
+        let mut diag_map = luminous_racks();
+        for pool in diag_map.osd_map.pools.iter_mut() {
+            if pool.is_erasure() {
+                pool.min_size = 1;
+            }
+        }
+        let pool = diag_map.osd_map.pool(3).unwrap();
+        assert_eq!(diag_map.required_members(pool), 2);
+        let simulation = diag_map.simulate_removal(&[0, 1, 2, 3, 4, 5]).unwrap();
+        assert!(simulation.inactive.iter().any(|pg| pg.pool == "ecpool"));
+    }
+
+    #[test]
+    fn simulate_placement_no_crush_map() {
+        assert!(jewel_safe().simulate_placement(&[0]).is_err());
+    }
+
+    #[test]
+    fn simulate_diag_unknown_osd() {
+        assert!(jewel_safe().simulate_diag(&[9], Format::Json).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::backend::CephBackend;
+use crate::cache::unix_time;
+use crate::diag::DiagMap;
+use crate::error::CSDError;
+use crate::from::FromFile;
+
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::prelude::*;
+
+// Bumped whenever the layout of the archive changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotMetadata {
+    pub fsid: String,
+    pub epoch: i32,
+    // Seconds since the Unix epoch
+    pub captured_at: u64,
+    pub ceph_version: Option<String>,
+}
+
+// The JSON output of every mon command the diag asked for while building its
+// maps, keyed by command, in one file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub version: u32,
+    pub metadata: SnapshotMetadata,
+    pub responses: BTreeMap<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CephVersion {
+    version: String,
+}
+
+// Passes mon commands on to `backend`, keeping a copy of each response
+struct Recorder<'a, B: CephBackend + ?Sized> {
+    backend: &'a B,
+    responses: RefCell<BTreeMap<String, Value>>,
+}
+
+impl<'a, B: CephBackend + ?Sized> CephBackend for Recorder<'a, B> {
+    fn mon_command(&self, cmd: &str) -> Result<String, CSDError> {
+        let response = self.backend.mon_command(cmd)?;
+        let value: Value = serde_json::from_str(&response)?;
+        self.responses.borrow_mut().insert(cmd.to_string(), value);
+        Ok(response)
+    }
+}
+
+impl Snapshot {
+    // Record everything `DiagMap::from_backend` fetches from `backend`, along
+    // with the cluster's fsid, OSD map epoch and ceph version
+    pub fn capture<B: CephBackend + ?Sized>(backend: &B) -> Result<Snapshot, CSDError> {
+        let recorder = Recorder {
+            backend,
+            responses: RefCell::new(BTreeMap::new()),
+        };
+        let diag_map = DiagMap::from_backend(&recorder)?;
+        // A snapshot without the CRUSH map would fail every check that
+        // recomputes placement, better not to write one
+        diag_map.crush_map()?;
+        // Older releases answer `version` with plain text, the version is
+        // only nice to have
+        let ceph_version = backend
+            .mon_command("version")
+            .ok()
+            .and_then(|output| serde_json::from_str::<CephVersion>(&output).ok())
+            .map(|version| version.version);
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            metadata: SnapshotMetadata {
+                fsid: diag_map.osd_map.fsid.clone(),
+                epoch: diag_map.osd_map.epoch,
+                captured_at: unix_time(),
+                ceph_version,
+            },
+            responses: recorder.responses.into_inner(),
+        })
+    }
+
+    // Read a snapshot written by `write`, refusing ones from a newer version
+    pub fn open(path: &str) -> Result<Snapshot, CSDError> {
+        let snapshot = Snapshot::from_file(path)?;
+        if snapshot.version > SNAPSHOT_VERSION {
+            return Err(CSDError::SnapshotVersion(snapshot.version));
+        }
+        Ok(snapshot)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), CSDError> {
+        let mut file = File::create(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+// A snapshot answers the commands it recorded, so every diag can run offline
+impl CephBackend for Snapshot {
+    fn mon_command(&self, cmd: &str) -> Result<String, CSDError> {
+        match self.responses.get(cmd) {
+            Some(response) => Ok(serde_json::to_string(response)?),
+            None => Err(CSDError::CephExecError(format!(
+                "`{}` is not in the snapshot",
+                cmd
+            ))),
+        }
+    }
+
+    fn collected_at(&self) -> u64 {
+        self.metadata.captured_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::diag::{Format, Status};
+
+    fn luminous_racks() -> MockBackend {
+        MockBackend::new()
+            .respond_from_file("osd dump", "test/luminous/osd_dump_racks.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/luminous/pg_dump_racks.json"))
+            .and_then(|backend| {
+                backend.respond_from_file("osd crush dump", "test/luminous/osd_crush_dump_racks.json")
+            })
+            .and_then(|backend| {
+                backend.respond_from_file(
+                    "osd erasure-code-profile get ec22",
+                    "test/luminous/erasure_code_profile_ec22.json",
+                )
+            })
+            .unwrap()
+            .respond(
+                "version",
+                "{\"version\":\"ceph version 12.2.13 (584a20eb0237c657dc0567da126be145106aa47e) luminous (stable)\"}",
+            )
+    }
+
+    #[test]
+    fn snapshot_capture() {
+        let snapshot = Snapshot::capture(&luminous_racks()).unwrap();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(snapshot.metadata.epoch, 311);
+        assert!(snapshot.metadata.ceph_version.unwrap().contains("luminous"));
+        let commands: Vec<&str> = snapshot.responses.keys().map(|cmd| cmd.as_str()).collect();
+        assert_eq!(
+            commands,
+            vec!["osd crush dump", "osd dump", "osd erasure-code-profile get ec22", "pg dump"]
+        );
+    }
+
+    #[test]
+    fn snapshot_capture_without_crush() {
+        let backend = MockBackend::new()
+            .respond_from_file("osd dump", "test/jewel/osd_dump_safe.json")
+            .and_then(|backend| backend.respond_from_file("pg dump", "test/jewel/pg_dump_safe.json"))
+            .unwrap()
+            .respond("osd crush dump", "not json");
+        match Snapshot::capture(&backend) {
+            Err(CSDError::CrushError(err)) => assert!(err.contains("JSON decoding error")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let path = std::env::temp_dir().join(format!("csd-snapshot-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let captured = Snapshot::capture(&luminous_racks()).unwrap();
+        captured.write(path).unwrap();
+        let snapshot = Snapshot::open(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(snapshot.metadata, captured.metadata);
+
+        let diag_map = DiagMap::from_backend(&snapshot).unwrap();
+        assert_eq!(diag_map.ec_profiles.len(), 1);
+        let status = diag_map.simulate_diag(&[5], Format::Json).unwrap();
+        assert_eq!(status, Status::Safe);
+    }
+
+    #[test]
+    fn snapshot_from_file() {
+        let snapshot = Snapshot::open("test/luminous/snapshot_racks.json").unwrap();
+        assert_eq!(snapshot.metadata.fsid, "5ad1ab4c-0fd1-4a8e-9a4b-6f2c41f3ac10");
+        let status = DiagMap::from_backend(&snapshot)
+            .unwrap()
+            .osd_diag(&[5], Format::Json)
+            .unwrap();
+        assert_eq!(status, Status::Safe);
+    }
+
+    #[test]
+    fn snapshot_newer_version() {
+        match Snapshot::open("test/luminous/snapshot_version_99.json") {
+            Err(CSDError::SnapshotVersion(99)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use ansi_term::Colour;
+use crate::backend::CephBackend;
+use crate::diag::{DiagMap, Format, Status};
+use crate::error::CSDError;
+use crate::exit::ExitStatus;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    // Time between two polls
+    pub interval: Duration,
+    // How long to wait for the OSDs to become removable before giving up
+    pub timeout: Duration,
+    // Polls in a row the OSDs must be removable for
+    pub consecutive: u32,
+}
+
+// One poll whose status differs from the one before it
+#[derive(Serialize, Debug)]
+struct WatchPoll {
+    status: Status,
+    epoch: Option<i32>,
+    elapsed: u64,
+    safe_polls: u32,
+    consecutive: u32,
+}
+
+impl WatchPoll {
+    fn print(&self, format: Format) {
+        match format {
+            Format::Pretty => {
+                let colour = match self.status {
+                    Status::Safe => Colour::Green,
+                    Status::Unknown => Colour::Yellow,
+                    Status::NonSafe => Colour::Red,
+                };
+                let epoch = self
+                    .epoch
+                    .map_or_else(|| "unknown epoch".to_string(), |epoch| format!("epoch {}", epoch));
+                println!(
+                    "{} {}s, {}: {} ({}/{} removable polls)",
+                    colour.paint("●"),
+                    self.elapsed,
+                    epoch,
+                    self.status,
+                    self.safe_polls,
+                    self.consecutive
+                );
+            }
+            Format::Json => {
+                if let Ok(json) = serde_json::to_string(self) {
+                    println!("{}", json);
+                }
+            }
+        }
+    }
+}
+
+// Fetch the maps from `backend` every `options.interval` until the OSDs in
+// `osd_ids`, or every OSD as with `exhaustive_diag` when there are none, have
+// been removable for `options.consecutive` polls in a row. Given OSDs have to
+// be removable together, see `joint_removal_status`. Only polls that
+// change the status are printed. A failure to fetch the maps on the first poll
+// is an error, later ones count as a pending poll so a mon election does not
+// end the wait.
+pub fn watch<B: CephBackend + ?Sized>(
+    backend: &B,
+    osd_ids: Option<&[i32]>,
+    options: WatchOptions,
+    format: Format,
+) -> Result<ExitStatus, CSDError> {
+    let start = Instant::now();
+    let consecutive = options.consecutive.max(1);
+    let mut last_status = None;
+    let mut safe_polls = 0;
+    loop {
+        let polled = DiagMap::from_backend(backend).and_then(|diag_map| {
+            let status = match osd_ids {
+                Some(osd_ids) => diag_map.joint_removal_status(osd_ids)?,
+                None => diag_map.removal_status(None)?,
+            };
+            Ok((status, diag_map.osd_map.epoch))
+        });
+        let (status, epoch) = match polled {
+            Ok((status, epoch)) => (status, Some(epoch)),
+            Err(err) => {
+                if last_status.is_none() {
+                    return Err(err);
+                }
+                warn!("Polling the cluster failed, {}", err);
+                (Status::Unknown, None)
+            }
+        };
+        if status == Status::Safe {
+            safe_polls += 1;
+        } else {
+            safe_polls = 0;
+        }
+        if last_status != Some(status) {
+            WatchPoll {
+                status,
+                epoch,
+                elapsed: start.elapsed().as_secs(),
+                safe_polls,
+                consecutive,
+            }
+            .print(format);
+            last_status = Some(status);
+        }
+
+        if safe_polls >= consecutive {
+            if let Format::Pretty = format {
+                println!(
+                    "{} Removable for {} polls in a row",
+                    Colour::Green.paint("●"),
+                    safe_polls
+                );
+            }
+            return Ok(ExitStatus::SafeRm);
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= options.timeout {
+            if let Format::Pretty = format {
+                println!(
+                    "{} Timed out after {}s, last status {}",
+                    Colour::Red.paint("●"),
+                    elapsed.as_secs(),
+                    status
+                );
+            }
+            return Ok(ExitStatus::Timeout);
+        }
+        thread::sleep(options.interval.min(options.timeout - elapsed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+
+    const PENDING: &str = "test/jewel/pg_dump_pending.json";
+    const SAFE: &str = "test/jewel/pg_dump_safe.json";
+
+    // A jewel cluster whose `pg dump` goes through `pg_dumps` in order
+    fn jewel_cluster(pg_dumps: &[&str]) -> MockBackend {
+        let mut backend = MockBackend::new()
+            .respond_from_file("osd dump", "test/jewel/osd_dump_safe.json")
+            .and_then(|backend| backend.respond_from_file("osd crush dump", "test/jewel/osd_crush_dump.json"))
+            .unwrap();
+        for pg_dump in pg_dumps {
+            backend = backend.respond_from_file("pg dump", pg_dump).unwrap();
+        }
+        backend
+    }
+
+    fn options(timeout: Duration, consecutive: u32) -> WatchOptions {
+        WatchOptions {
+            interval: Duration::from_millis(0),
+            timeout,
+            consecutive,
+        }
+    }
+
+    fn pg_dumps(backend: &MockBackend) -> usize {
+        backend.calls().iter().filter(|cmd| *cmd == "pg dump").count()
+    }
+
+    #[test]
+    fn watch_until_safe() {
+        let backend = jewel_cluster(&[PENDING, PENDING, SAFE]);
+        let exit = watch(&backend, Some(&[0]), options(Duration::from_secs(60), 3), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::SafeRm);
+        // Two pending polls, then three safe ones
+        assert_eq!(pg_dumps(&backend), 5);
+    }
+
+    #[test]
+    fn watch_safe_blip() {
+        let backend = jewel_cluster(&[PENDING, SAFE, PENDING, SAFE]);
+        let exit = watch(&backend, None, options(Duration::from_secs(60), 2), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::SafeRm);
+        // The first safe poll does not count once the next one is pending
+        assert_eq!(pg_dumps(&backend), 5);
+    }
+
+    #[test]
+    fn watch_timeout() {
+        let backend = jewel_cluster(&[PENDING]);
+        let exit = watch(&backend, Some(&[0]), options(Duration::from_millis(20), 1), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::Timeout);
+        assert!(pg_dumps(&backend) > 1);
+    }
+
+    #[test]
+    fn watch_together() {
+        // osd.0 and osd.1 are each removable, but not both at once
+        let backend = jewel_cluster(&[SAFE]);
+        let exit = watch(&backend, Some(&[0, 1]), options(Duration::from_millis(20), 1), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::Timeout);
+        let exit = watch(&backend, Some(&[0]), options(Duration::from_millis(20), 1), Format::Json);
+        assert_eq!(exit.unwrap(), ExitStatus::SafeRm);
+    }
+
+    #[test]
+    fn watch_unknown_osd() {
+        let backend = jewel_cluster(&[SAFE]);
+        let exit = watch(&backend, Some(&[42]), options(Duration::from_secs(60), 1), Format::Json);
+        assert!(exit.is_err());
+        assert_eq!(pg_dumps(&backend), 1);
+    }
+}
@@ -1,13 +1,25 @@
 use std::env;
+use std::fmt;
+use std::io::{self, Write};
 use std::process;
+use std::str::FromStr;
+use std::time::Duration;
 
+use ceph_safe_disk::backend::{CephBackend, CliBackend};
 use ceph_safe_disk::diag::{DiagMap, Format, Status};
+use ceph_safe_disk::drain::{drain, wait_drained, DrainMethod, DrainOptions};
 use ceph_safe_disk::exec::check_user;
 use ceph_safe_disk::exit::ExitStatus;
+use ceph_safe_disk::metrics::{export, write_textfile};
+use ceph_safe_disk::nagios::{nagios, NagiosCheck, NagiosThresholds};
+use ceph_safe_disk::remove::{remove_osds, RemoveMethod};
+use ceph_safe_disk::serve::serve;
+use ceph_safe_disk::snapshot::Snapshot;
+use ceph_safe_disk::watch::{watch, WatchOptions};
 
-pub static NAME: &'static str = "ceph-safe-disk";
+pub static NAME: &str = "ceph-safe-disk";
 
-use getopts::Options;
+use getopts::{Matches, Options};
 
 fn print_help(opts: &Options) {
     println!("{0}", opts.usage("Usage: ceph-safe-disk [OPTION]"));
@@ -15,12 +27,87 @@ fn print_help(opts: &Options) {
         "Exit statuses:
     0: Safe to remove an OSD
     1: Not safe to remove an OSD
-    2: General error"
+    2: General error
+    3: Pending, PGs are still settling and the check should be retried
+    4: Timed out waiting for OSDs with --watch or --drain"
     );
 }
 
+// Parse a comma separated list of OSD ids. Both `3` and `osd.3` are accepted.
+fn parse_osd_ids(arg: &str) -> Result<Vec<i32>, String> {
+    arg.split(',')
+        .map(|id| id.trim())
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.trim_start_matches("osd.")
+                .parse::<i32>()
+                .map_err(|_| format!("Invalid OSD id `{}`", id))
+        })
+        .collect()
+}
+
+// Read an OSD id list option, erroring out if it was given but is empty
+fn osd_ids_opt(matches: &Matches, opt: &str) -> Result<Option<Vec<i32>>, String> {
+    match matches.opt_str(opt) {
+        Some(arg) => match parse_osd_ids(&arg)? {
+            ref ids if ids.is_empty() => Err("No OSD ids given".to_string()),
+            ids => Ok(Some(ids)),
+        },
+        None => Ok(None),
+    }
+}
+
+// Read a numeric option, falling back to `default` when it was not given
+fn number_opt<T: FromStr>(matches: &Matches, opt: &str, default: T) -> Result<T, String> {
+    match matches.opt_str(opt) {
+        Some(arg) => arg
+            .trim()
+            .parse::<T>()
+            .map_err(|_| format!("Invalid number `{}` for --{}", arg, opt)),
+        None => Ok(default),
+    }
+}
+
+// Ask `question` on the terminal, only `y` or `yes` count as a yes
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_lowercase().as_ref(), "y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+// Map the result of a diag to an exit status, printing any error
+fn exit_status<E: fmt::Display>(result: Result<Status, E>) -> i32 {
+    match result {
+        Ok(status) => ExitStatus::from(status) as i32,
+        Err(err) => {
+            println!("{}: {}", NAME, err);
+            ExitStatus::Err as i32
+        }
+    }
+}
+
+// Print an error that stopped the checks from running. As a Nagios plugin it
+// has to be an UNKNOWN line and exit code, whatever went wrong.
+fn setup_error<E: fmt::Display>(nagios_mode: bool, err: E) -> i32 {
+    if nagios_mode {
+        let check = NagiosCheck::unknown(err.to_string());
+        println!("{}", check.line());
+        check.exit_code()
+    } else {
+        println!("{}: {}", NAME, err);
+        ExitStatus::Err as i32
+    }
+}
+
 fn run() -> i32 {
     let args: Vec<String> = env::args().collect();
+    // Known before the options are parsed, so that a bad option is reported
+    // the way Nagios expects too
+    let nagios_mode = args[1..].iter().any(|arg| arg == "--nagios");
     let mut options = Options::new();
 
     options.optflag("h", "help", "Print help information");
@@ -34,6 +121,151 @@ fn run() -> i32 {
         "exhaustive",
         "Give an exhaustive status of removable OSDs",
     );
+    options.optopt(
+        "o",
+        "osd",
+        "Give the status of only the given OSDs, e.g. 3,7",
+        "ID[,ID...]",
+    );
+    options.optflag(
+        "x",
+        "explain",
+        "With -e or -o, list the PGs behind each OSD's status",
+    );
+    options.optopt(
+        "s",
+        "simulate",
+        "Simulate removing all of the given OSDs at once, e.g. 3,7",
+        "ID[,ID...]",
+    );
+    options.optopt(
+        "r",
+        "remap",
+        "Recompute CRUSH placement with the given OSDs weighted to zero",
+        "ID[,ID...]",
+    );
+    options.optopt(
+        "c",
+        "capacity",
+        "Project OSD utilisation once the given OSDs' data has moved",
+        "ID[,ID...]",
+    );
+    options.optopt(
+        "m",
+        "movement",
+        "Estimate the data moved by removing the given OSDs",
+        "ID[,ID...]",
+    );
+    options.optopt(
+        "H",
+        "host",
+        "Simulate removing every OSD under a CRUSH bucket such as a host or rack",
+        "BUCKET",
+    );
+    options.optflag(
+        "w",
+        "watch",
+        "Poll until the OSDs given with -o, or all OSDs, are removable. With -D, only wait for the given OSDs to drain",
+    );
+    options.optopt(
+        "D",
+        "drain",
+        "Mark the given OSDs out and wait for their PGs to move off them",
+        "ID[,ID...]",
+    );
+    options.optflag(
+        "",
+        "reweight",
+        "With -D or --plan, set the OSDs' CRUSH weight to 0 instead of marking them out",
+    );
+    options.optopt(
+        "",
+        "destroy",
+        "Destroy the given OSDs, keeping their ids, once they are removable",
+        "ID[,ID...]",
+    );
+    options.optopt(
+        "",
+        "purge",
+        "Purge the given OSDs from the cluster once they are removable",
+        "ID[,ID...]",
+    );
+    options.optopt(
+        "",
+        "plan",
+        "Print the commands to destroy or purge the OSDs given with -o or under the bucket given with -H, as a shell script",
+        "destroy|purge",
+    );
+    options.optflag(
+        "y",
+        "yes",
+        "With --destroy or --purge, do not ask for confirmation",
+    );
+    options.optopt(
+        "",
+        "interval",
+        "With -w or -D, seconds between polls, 10 by default",
+        "SECS",
+    );
+    options.optopt(
+        "",
+        "timeout",
+        "With -w or -D, seconds to wait before giving up, 3600 by default",
+        "SECS",
+    );
+    options.optopt(
+        "",
+        "consecutive",
+        "With -w, polls in a row the OSDs must be removable for, 3 by default",
+        "N",
+    );
+    options.optopt(
+        "",
+        "capture",
+        "Record everything the checks read from the cluster into FILE",
+        "FILE",
+    );
+    options.optopt(
+        "",
+        "from-snapshot",
+        "Run the checks against a file written by --capture",
+        "FILE",
+    );
+    options.optopt(
+        "",
+        "exporter",
+        "Serve Prometheus metrics on ADDR, e.g. 0.0.0.0:9284, refreshed every 60 seconds or --interval",
+        "ADDR",
+    );
+    options.optopt(
+        "",
+        "textfile",
+        "Write Prometheus metrics to FILE for node_exporter's textfile collector",
+        "FILE",
+    );
+    options.optopt(
+        "",
+        "serve",
+        "Answer removability queries with a JSON API on ADDR, e.g. 127.0.0.1:9285, refreshed every 60 seconds or --interval",
+        "ADDR",
+    );
+    options.optflag(
+        "",
+        "nagios",
+        "Check the cluster as a Nagios plugin, with its output and exit codes",
+    );
+    options.optopt(
+        "",
+        "warning",
+        "With --nagios, warn when fewer than N OSDs are removable, 1 by default",
+        "N",
+    );
+    options.optopt(
+        "",
+        "critical",
+        "With --nagios, go critical when a pool's margin over min_size is N or less, 0 by default",
+        "N",
+    );
     options.optopt(
         "f",
         "format",
@@ -43,19 +275,34 @@ fn run() -> i32 {
 
     let matches = match options.parse(&args[1..]) {
         Ok(m) => m,
-        Err(err) => {
-            println!("{}: {}", NAME, err.to_string());
-            process::exit(ExitStatus::Err as i32);
-        }
+        Err(err) => process::exit(setup_error(nagios_mode, err)),
     };
 
     if matches.opt_present("h") {
         print_help(&options);
-    } else {
+    } else if let Some(path) = matches.opt_str("capture") {
         if let Err(user_err) = check_user() {
-            println!("{}: {}", NAME, user_err.to_string());
+            println!("{}: {}", NAME, user_err);
             return ExitStatus::Err as i32;
         };
+        let captured = Snapshot::capture(&CliBackend).and_then(|snapshot| {
+            snapshot.write(&path)?;
+            Ok(snapshot)
+        });
+        match captured {
+            Ok(snapshot) => {
+                println!(
+                    "Captured cluster {} at OSD map epoch {} to {}",
+                    snapshot.metadata.fsid, snapshot.metadata.epoch, path
+                );
+                return 0;
+            }
+            Err(err) => {
+                println!("{}: {}", NAME, err);
+                return ExitStatus::Err as i32;
+            }
+        }
+    } else {
         let mut format = Format::Pretty;
         if matches.opt_present("f") {
             if let Some(format_arg) = matches.opt_str("f") {
@@ -64,24 +311,243 @@ fn run() -> i32 {
                 }
             }
         }
-        match DiagMap::new() {
+        let backend: Box<dyn CephBackend> = match matches.opt_str("from-snapshot") {
+            Some(path) => match Snapshot::open(&path) {
+                Ok(snapshot) => Box::new(snapshot),
+                Err(err) => return setup_error(nagios_mode, err),
+            },
+            None => {
+                if let Err(user_err) = check_user() {
+                    return setup_error(nagios_mode, user_err);
+                };
+                Box::new(CliBackend)
+            }
+        };
+        if matches.opt_present("w") && !matches.opt_present("D") {
+            let watched = osd_ids_opt(&matches, "o").and_then(|osd_ids| {
+                let options = WatchOptions {
+                    interval: Duration::from_secs(number_opt(&matches, "interval", 10)?),
+                    timeout: Duration::from_secs(number_opt(&matches, "timeout", 3600)?),
+                    consecutive: number_opt(&matches, "consecutive", 3)?,
+                };
+                Ok((osd_ids, options))
+            });
+            return match watched {
+                Ok((osd_ids, options)) => {
+                    match watch(backend.as_ref(), osd_ids.as_deref(), options, format) {
+                        Ok(exit) => exit as i32,
+                        Err(err) => {
+                            println!("{}: {}", NAME, err);
+                            ExitStatus::Err as i32
+                        }
+                    }
+                }
+                Err(err) => {
+                    println!("{}: {}", NAME, err);
+                    ExitStatus::Err as i32
+                }
+            };
+        }
+        if matches.opt_present("nagios") {
+            let thresholds = number_opt(&matches, "warning", 1).and_then(|removable| {
+                Ok(NagiosThresholds {
+                    removable,
+                    margin: number_opt(&matches, "critical", 0)?,
+                })
+            });
+            return match thresholds {
+                Ok(thresholds) => {
+                    let check = nagios(backend.as_ref(), thresholds);
+                    println!("{}", check.line());
+                    check.exit_code()
+                }
+                Err(err) => setup_error(nagios_mode, err),
+            };
+        }
+        if let Some(addr) = matches.opt_str("exporter") {
+            let exported = number_opt(&matches, "interval", 60)
+                .map_err(|err| err.to_string())
+                .and_then(|interval| {
+                    export(backend.as_ref(), &addr, Duration::from_secs(interval))
+                        .map_err(|err| err.to_string())
+                });
+            if let Err(err) = exported {
+                println!("{}: {}", NAME, err);
+            }
+            return ExitStatus::Err as i32;
+        }
+        if let Some(addr) = matches.opt_str("serve") {
+            let served = number_opt(&matches, "interval", 60)
+                .map_err(|err| err.to_string())
+                .and_then(|interval| {
+                    serve(backend.as_ref(), &addr, Duration::from_secs(interval))
+                        .map_err(|err| err.to_string())
+                });
+            if let Err(err) = served {
+                println!("{}: {}", NAME, err);
+            }
+            return ExitStatus::Err as i32;
+        }
+        if matches.opt_present("D") {
+            let drained = osd_ids_opt(&matches, "D").and_then(|osd_ids| {
+                let options = DrainOptions {
+                    method: if matches.opt_present("reweight") {
+                        DrainMethod::Reweight
+                    } else {
+                        DrainMethod::Out
+                    },
+                    interval: Duration::from_secs(number_opt(&matches, "interval", 10)?),
+                    timeout: Duration::from_secs(number_opt(&matches, "timeout", 3600)?),
+                };
+                Ok((osd_ids.unwrap_or_default(), options))
+            });
+            return match drained {
+                Ok((osd_ids, options)) => {
+                    let drained = if matches.opt_present("w") {
+                        // The OSDs were emptied already, only wait on them
+                        wait_drained(backend.as_ref(), &osd_ids, options, format)
+                    } else {
+                        drain(backend.as_ref(), &osd_ids, options, format)
+                    };
+                    match drained {
+                        Ok(exit) => exit as i32,
+                        Err(err) => {
+                            println!("{}: {}", NAME, err);
+                            ExitStatus::Err as i32
+                        }
+                    }
+                }
+                Err(err) => {
+                    println!("{}: {}", NAME, err);
+                    ExitStatus::Err as i32
+                }
+            };
+        }
+        if matches.opt_present("destroy") || matches.opt_present("purge") {
+            let (opt, method) = if matches.opt_present("destroy") {
+                ("destroy", RemoveMethod::Destroy)
+            } else {
+                ("purge", RemoveMethod::Purge)
+            };
+            let osd_ids = match osd_ids_opt(&matches, opt) {
+                Ok(ids) => ids.unwrap_or_default(),
+                Err(err) => {
+                    println!("{}: {}", NAME, err);
+                    return ExitStatus::Err as i32;
+                }
+            };
+            let yes = matches.opt_present("y");
+            return match remove_osds(backend.as_ref(), &osd_ids, method, |question| {
+                yes || confirm(question)
+            }) {
+                Ok(record) => {
+                    record.print(format);
+                    record.exit_status() as i32
+                }
+                Err(err) => {
+                    println!("{}: {}", NAME, err);
+                    ExitStatus::Err as i32
+                }
+            };
+        }
+        let collected_at = backend.collected_at();
+        match DiagMap::from_backend(backend.as_ref()) {
             Ok(diag_map) => {
-                if matches.opt_present("q") {
-                    if diag_map.quick_diag(format) {
-                        return ExitStatus::SafeRm as i32;
+                let osd_ids = match osd_ids_opt(&matches, "o") {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        println!("{}: {}", NAME, err);
+                        return ExitStatus::Err as i32;
+                    }
+                };
+                let simulate_ids = match osd_ids_opt(&matches, "s") {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        println!("{}: {}", NAME, err);
+                        return ExitStatus::Err as i32;
+                    }
+                };
+                let remap_ids = match osd_ids_opt(&matches, "r") {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        println!("{}: {}", NAME, err);
+                        return ExitStatus::Err as i32;
+                    }
+                };
+                let capacity_ids = match osd_ids_opt(&matches, "c") {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        println!("{}: {}", NAME, err);
+                        return ExitStatus::Err as i32;
+                    }
+                };
+                let movement_ids = match osd_ids_opt(&matches, "m") {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        println!("{}: {}", NAME, err);
+                        return ExitStatus::Err as i32;
+                    }
+                };
+                if let Some(path) = matches.opt_str("textfile") {
+                    return match write_textfile(&diag_map, collected_at, &path) {
+                        Ok(()) => 0,
+                        Err(err) => {
+                            println!("{}: {}", NAME, err);
+                            ExitStatus::Err as i32
+                        }
+                    };
+                } else if let Some(method) = matches.opt_str("plan") {
+                    let method = match method.as_ref() {
+                        "destroy" => RemoveMethod::Destroy,
+                        "purge" => RemoveMethod::Purge,
+                        _ => {
+                            println!("{}: Invalid plan `{}`, use destroy or purge", NAME, method);
+                            return ExitStatus::Err as i32;
+                        }
+                    };
+                    let drain_method = if matches.opt_present("reweight") {
+                        DrainMethod::Reweight
                     } else {
-                        return ExitStatus::NonSafeRm as i32;
+                        DrainMethod::Out
+                    };
+                    let bucket = matches.opt_str("H");
+                    if osd_ids.is_none() && bucket.is_none() {
+                        println!("{}: --plan needs OSDs with -o or a bucket with -H", NAME);
+                        return ExitStatus::Err as i32;
                     }
-                } else if matches.opt_present("e") {
-                    match diag_map.exhaustive_diag(format) {
-                        Status::Safe => return ExitStatus::SafeRm as i32,
-                        Status::NonSafe => return ExitStatus::NonSafeRm as i32,
-                        _ => return ExitStatus::Err as i32,
+                    if osd_ids.is_some() && bucket.is_some() {
+                        println!("{}: --plan takes either -o or -H, not both", NAME);
+                        return ExitStatus::Err as i32;
                     }
+                    return exit_status(diag_map.plan_diag(
+                        &osd_ids.unwrap_or_default(),
+                        bucket.as_deref(),
+                        drain_method,
+                        method,
+                        format,
+                    ));
+                } else if matches.opt_present("x") && (osd_ids.is_some() || matches.opt_present("e")) {
+                    return exit_status(diag_map.explain_diag(osd_ids.as_deref(), format));
+                } else if let Some(osd_ids) = osd_ids {
+                    return exit_status(diag_map.osd_diag(&osd_ids, format));
+                } else if let Some(simulate_ids) = simulate_ids {
+                    return exit_status(diag_map.simulate_diag(&simulate_ids, format));
+                } else if let Some(remap_ids) = remap_ids {
+                    return exit_status(diag_map.placement_diag(&remap_ids, format));
+                } else if let Some(capacity_ids) = capacity_ids {
+                    return exit_status(diag_map.capacity_diag(&capacity_ids, format));
+                } else if let Some(movement_ids) = movement_ids {
+                    return exit_status(diag_map.movement_diag(&movement_ids, format));
+                } else if let Some(bucket) = matches.opt_str("H") {
+                    return exit_status(diag_map.bucket_diag(&bucket, format));
+                } else if matches.opt_present("q") {
+                    return ExitStatus::from(diag_map.quick_diag(format)) as i32;
+                } else if matches.opt_present("e") {
+                    return ExitStatus::from(diag_map.exhaustive_diag(format)) as i32;
                 }
             }
             Err(err) => {
-                print!("{}: {}", NAME, err.to_string());
+                println!("{}: {}", NAME, err);
                 return ExitStatus::Err as i32;
             }
         }
@@ -91,7 +557,7 @@ fn run() -> i32 {
 
 fn main() {
     match run() {
-        error @ 0...2 => process::exit(error),
+        error @ 0..=4 => process::exit(error),
         _ => process::exit(ExitStatus::Err as i32),
     };
 }